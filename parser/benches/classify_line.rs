@@ -0,0 +1,44 @@
+use std::hint::black_box;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use parser::line::classify_line;
+
+/// Roughly the mix of lines in a real chart: a short header, then a body
+/// that is overwhelmingly channel data.
+fn chart() -> String {
+    let mut chart = String::from(
+        "*---------------------- HEADER FIELD\n\
+         #PLAYER 1\n\
+         #GENRE Trance\n\
+         #TITLE Example\n\
+         #ARTIST someone\n\
+         #BPM 150\n\
+         #PLAYLEVEL 12\n\
+         #RANK 2\n\
+         \n",
+    );
+    for id in 1..200 {
+        chart.push_str(&format!("#WAV{id:02} sound{id}.wav\n"));
+    }
+    chart.push_str("\n*---------------------- MAIN DATA FIELD\n");
+    for measure in 0..300 {
+        for channel in ["01", "11", "12", "13", "14", "15", "16"] {
+            chart.push_str(&format!("#{measure:03}{channel}:0A000B000C000D00\n"));
+        }
+    }
+    chart
+}
+
+fn classify(c: &mut Criterion) {
+    let chart = chart();
+    c.bench_function("classify_line", |b| {
+        b.iter(|| {
+            for line in black_box(&chart).lines() {
+                black_box(classify_line(line));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, classify);
+criterion_main!(benches);
@@ -0,0 +1,261 @@
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::error::ParseError;
+use crate::fraction::{Fraction, lcm};
+use crate::header::Base;
+use crate::id::ObjectId;
+
+/// The objects on one channel-data line, `#xxxCC:data`.
+///
+/// Data is a string of two character pairs, each one an equally spaced slot in
+/// the measure. `00` is an empty slot. `#00111:00AA00BB` places `AA` on the
+/// second quarter and `BB` on the last.
+///
+/// How a pair is read depends on the channel, so we keep the distinction
+/// around rather than making every consumer special case channel codes.
+///
+/// Channel `02` isn't object data at all, it's a measure length, and doesn't
+/// belong here.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelData {
+    /// Two digit hexadecimal values.
+    ///
+    /// Only channel `03`, where each pair is an integer BPM from 1 to 255.
+    Hex(Vec<u8>),
+    /// References to a definition by id, such as a `#WAVxx` or `#BPMxx`. Despite
+    /// the name, these are read in base 62 under `#BASE 62`.
+    Base36(Vec<ObjectId>),
+}
+
+impl ChannelData {
+    /// Parses the data of a channel line, picking the representation from the
+    /// channel code. Ids are read in the chart's `base`.
+    pub fn parse(channel: &str, data: &str, base: &Base) -> Result<Self, ParseError> {
+        let invalid = || ParseError::InvalidChannelData {
+            channel: channel.to_string(),
+            data: data.to_string(),
+        };
+
+        let data = data.trim();
+        if !data.len().is_multiple_of(2) || !data.is_ascii() {
+            return Err(invalid());
+        }
+        let pairs = (0..data.len()).step_by(2).map(|i| &data[i..i + 2]);
+
+        if is_hex_channel(channel) {
+            pairs
+                .map(|pair| hex_pair(pair).ok_or_else(invalid))
+                .collect::<Result<_, _>>()
+                .map(Self::Hex)
+        } else {
+            pairs
+                .map(|pair| ObjectId::parse(pair, base).ok_or_else(invalid))
+                .collect::<Result<_, _>>()
+                .map(Self::Base36)
+        }
+    }
+
+    /// The number of slots the measure is divided into.
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Hex(values) => values.len(),
+            Self::Base36(ids) => ids.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of slots holding an object, rather than `00`.
+    pub fn object_count(&self) -> usize {
+        match self {
+            Self::Hex(values) => values.iter().filter(|&&value| value != 0).count(),
+            Self::Base36(ids) => ids.iter().filter(|id| id.value() != 0).count(),
+        }
+    }
+
+    /// Combines this line with a `later` line of the same channel in the same
+    /// measure.
+    ///
+    /// A channel can appear more than once in a measure, and the objects of
+    /// every line count, so `#01111:0100` and `#01111:0001` are two notes. The
+    /// merged data is divided finely enough to hold both, and where both put
+    /// an object on the same slot, the later one wins.
+    ///
+    /// This isn't for the BGM channel `01`. Each of its lines is a separate
+    /// layer, and two sounds on the same slot both play, so merging
+    /// `#00101:AA00` with `#00101:BB00` would lose `AA`. Keep those lines apart.
+    ///
+    /// Data for different kinds of channel can't be merged, and `later` is
+    /// returned as is.
+    pub fn merge(&self, later: &ChannelData) -> ChannelData {
+        match (self, later) {
+            (Self::Hex(earlier), Self::Hex(later)) => {
+                Self::Hex(merge_slots(earlier, later, 0, |&value| value != 0))
+            }
+            (Self::Base36(earlier), Self::Base36(later)) => {
+                Self::Base36(merge_slots(earlier, later, ObjectId::ZERO, |id| {
+                    *id != ObjectId::ZERO
+                }))
+            }
+            _ => later.clone(),
+        }
+    }
+
+    /// Where in the measure the given slot lands.
+    ///
+    /// # Panics
+    /// If the data has no slots.
+    pub fn position(&self, slot: usize) -> Fraction {
+        Fraction::new(slot as u64, self.len() as u64)
+    }
+}
+
+/// The side of the play area a note channel belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    P1,
+    P2,
+}
+
+/// Which side a channel holds notes for, if it holds notes at all.
+///
+/// Visible (`1x`/`2x`), invisible (`3x`/`4x`), long (`5x`/`6x`) and landmine
+/// (`Dx`/`Ex`) channels all count, for lanes `1` to `9`.
+pub fn note_side(channel: &str) -> Option<Side> {
+    let &[kind, lane] = channel.as_bytes() else {
+        return None;
+    };
+    if !matches!(lane, b'1'..=b'9') {
+        return None;
+    }
+    match kind.to_ascii_uppercase() {
+        b'1' | b'3' | b'5' | b'D' => Some(Side::P1),
+        b'2' | b'4' | b'6' | b'E' => Some(Side::P2),
+        _ => None,
+    }
+}
+
+/// Lays `earlier` and then `later` out over a common subdivision of both.
+fn merge_slots<T: Copy>(
+    earlier: &[T],
+    later: &[T],
+    empty: T,
+    is_object: impl Fn(&T) -> bool,
+) -> Vec<T> {
+    if earlier.is_empty() {
+        return later.to_vec();
+    }
+    if later.is_empty() {
+        return earlier.to_vec();
+    }
+
+    let len = lcm(earlier.len() as u64, later.len() as u64) as usize;
+    let mut merged = vec![empty; len];
+    for slots in [earlier, later] {
+        let stride = len / slots.len();
+        for (slot, value) in slots.iter().enumerate() {
+            if is_object(value) {
+                merged[slot * stride] = *value;
+            }
+        }
+    }
+    merged
+}
+
+fn hex_pair(pair: &str) -> Option<u8> {
+    // from_str_radix would also take a sign, as in `+F`.
+    if !pair.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return None;
+    }
+    u8::from_str_radix(pair, 16).ok()
+}
+
+fn is_hex_channel(channel: &str) -> bool {
+    channel == "03"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> Vec<ObjectId> {
+        ids.iter()
+            .map(|id| ObjectId::from_base36(id).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn representation_follows_the_channel() {
+        assert_eq!(
+            ChannelData::parse("03", "00FF7f", &Base::Base36).unwrap(),
+            ChannelData::Hex(vec![0, 255, 127])
+        );
+        assert_eq!(
+            ChannelData::parse("11", "00ZZ7f", &Base::Base36).unwrap(),
+            ChannelData::Base36(ids(&["00", "ZZ", "7F"]))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_data() {
+        assert!(ChannelData::parse("11", "0", &Base::Base36).is_err());
+        assert!(ChannelData::parse("11", "0!", &Base::Base36).is_err());
+        assert!(ChannelData::parse("03", "0Z", &Base::Base36).is_err());
+        assert!(ChannelData::parse("03", "+F", &Base::Base36).is_err());
+    }
+
+    #[test]
+    fn counts() {
+        let data = ChannelData::parse("11", "00AA00BB", &Base::Base36).unwrap();
+        assert_eq!(data.len(), 4);
+        assert_eq!(data.object_count(), 2);
+        assert!(
+            ChannelData::parse("11", "", &Base::Base36)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn merge_keeps_objects_from_both_lines() {
+        let earlier = ChannelData::parse("11", "0100", &Base::Base36).unwrap();
+        let later = ChannelData::parse("11", "0001", &Base::Base36).unwrap();
+        let merged = earlier.merge(&later);
+        assert_eq!(merged, ChannelData::Base36(ids(&["01", "01"])));
+        assert_eq!(merged.object_count(), 2);
+    }
+
+    #[test]
+    fn merge_subdivides_to_fit_both() {
+        let earlier = ChannelData::parse("11", "AA00", &Base::Base36).unwrap();
+        let later = ChannelData::parse("11", "00BB00", &Base::Base36).unwrap();
+        assert_eq!(
+            earlier.merge(&later),
+            ChannelData::Base36(ids(&["AA", "00", "BB", "00", "00", "00"]))
+        );
+    }
+
+    #[test]
+    fn merge_later_wins_a_shared_slot() {
+        let earlier = ChannelData::parse("03", "7800", &Base::Base36).unwrap();
+        let later = ChannelData::parse("03", "9600", &Base::Base36).unwrap();
+        assert_eq!(earlier.merge(&later), ChannelData::Hex(vec![0x96, 0]));
+
+        let empty = ChannelData::parse("11", "", &Base::Base36).unwrap();
+        assert_eq!(empty.merge(&later), later);
+    }
+
+    #[test]
+    fn base_62_data() {
+        let data = ChannelData::parse("11", "aaAA", &Base::Base62).unwrap();
+        let ChannelData::Base36(ids) = data else {
+            panic!();
+        };
+        assert_ne!(ids[0], ids[1]);
+        assert_eq!(ids[0], ObjectId::from_base62("aa").unwrap());
+    }
+}
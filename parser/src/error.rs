@@ -0,0 +1,205 @@
+use alloc::string::String;
+use core::fmt;
+
+use crate::header::{Player, Rank};
+use crate::id::ObjectId;
+
+/// Something wrong enough with a chart that we can't make sense of it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// A command's argument isn't the kind of value the command takes.
+    InvalidValue { command: String, value: String },
+    /// An object identifier that isn't two base-36 digits, like the `G!` in `#WAVG!`.
+    InvalidIdentifier { command: String, id: String },
+    /// Channel data that isn't a string of valid pairs for its channel.
+    InvalidChannelData { channel: String, data: String },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidValue { command, value } => {
+                write!(f, "invalid value {value:?} for #{command}")
+            }
+            Self::InvalidIdentifier { command, id } => {
+                write!(f, "invalid identifier {id:?} for #{command}")
+            }
+            Self::InvalidChannelData { channel, data } => {
+                write!(f, "invalid data {data:?} for channel {channel}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+/// Something that makes a chart unplayable, found by
+/// [`check_playable`](crate::validate::check_playable).
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    /// Channel data plays a `#WAVxx` that was never defined.
+    UndefinedWav { channel: String, id: ObjectId },
+    /// A `#WAVxx` or `#BMPxx` file that isn't where the chart says it is.
+    MissingResource { filename: String },
+    /// `#BPM` isn't positive, or a `#BPMxx` is zero.
+    InvalidBpm { bpm: f32 },
+    /// There isn't a single note to hit.
+    NoNotes,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedWav { channel, id } => {
+                write!(f, "channel {channel} plays undefined #WAV{id}")
+            }
+            Self::MissingResource { filename } => write!(f, "missing file {filename:?}"),
+            Self::InvalidBpm { bpm } => write!(f, "unplayable BPM {bpm}"),
+            Self::NoNotes => f.write_str("no notes to hit"),
+        }
+    }
+}
+
+impl core::error::Error for ValidationError {}
+
+/// Something odd about a chart that we recovered from.
+///
+/// Charts in the wild are messy, so most oddities get one of these rather
+/// than an error. It's up to the caller whether they care.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseWarning {
+    /// A command's argument isn't the kind of value the command takes, so the
+    /// command was ignored.
+    InvalidValue { command: String, value: String },
+    /// A `#STOP` duration had a fractional part, which was dropped.
+    FractionalStopTruncated { id: ObjectId, value: String },
+    /// A `#STOP` duration was negative, and was treated as 0.
+    NegativeStop { id: ObjectId, value: String },
+    /// An `#ARGB` named a BGA layer that doesn't exist, and was ignored.
+    UnknownBgaLayer { layer: String },
+    /// The 2P side has notes but `#PLAYER` says single play, or the other way
+    /// around for couple and double play.
+    PlayerSideMismatch { player: Player, uses_p2: bool },
+    /// A measure's channel data doesn't divide it cleanly: data with an odd
+    /// number of characters, or a second `02` that gives the measure a
+    /// different length. `channel` is `02` for the latter.
+    InconsistentSubdivision { measure: String, channel: String },
+    /// A command from an old player that nothing supports any more. It's
+    /// kept if it can be, but has no effect.
+    ObsoleteCommand { command: String },
+    /// An `#OPTION` named a play option we don't know, which was ignored.
+    UnknownPlayOption { option: String },
+    /// A `#URL` that doesn't look like a URL. It's kept as written.
+    MalformedUrl { value: String },
+    /// An `#EMAIL` that doesn't look like an address. It's kept as written.
+    MalformedEmail { value: String },
+    /// A `#PLAYLEVEL` above what the caller considers a usual level.
+    HighPlayLevel { level: i32 },
+    /// The file ended part way through a line that couldn't be parsed, so it
+    /// was most likely cut off. Everything before it was kept.
+    TruncatedInput { line: String },
+    /// Channel data followed by whitespace, which was ignored.
+    TrailingWhitespace { measure: String, channel: String },
+    /// A numeric argument written with full-width digits, as in `#BPM １３０`.
+    /// They were read as their ASCII counterparts.
+    FullWidthDigits { command: String, value: String },
+    /// The chart has both `#RANK` and `#DEFEXRANK`. `#DEFEXRANK` is used.
+    RankAndDefexrank { rank: Rank, defexrank: f32 },
+    /// A second `#SUBTITLE` replaced `replaced`.
+    DuplicateSubtitle { replaced: String },
+    /// A decimal argument in scientific notation, as in `#BPM 1.3e2`. The
+    /// command was ignored.
+    ScientificNotation { command: String, value: String },
+    /// A decimal argument with a comma, as in `#BPM 1,5`. It was read as a
+    /// decimal point, unless exactly three digits follow it, as in
+    /// `#TOTAL 1,000`, where it may be a thousands separator and the command
+    /// was ignored.
+    DecimalComma { command: String, value: String },
+    /// A one character object id, as in `#WAV1`. It was read with a leading
+    /// `0`, as `#WAV01`.
+    ShortIdentifier { command: String, id: String },
+    /// A `#BASE` after some object definitions. The earlier ids were read in
+    /// the base before it, which may not be what the author meant.
+    BaseAfterDefinitions,
+}
+
+/// How much a [`ParseWarning`] matters, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Worth knowing about, but plays exactly as the author meant.
+    Info,
+    /// Probably not what the author meant, though the chart still plays.
+    Warning,
+    /// A mistake in the chart. It plays, but not the way it should.
+    Error,
+}
+
+impl ParseWarning {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::FractionalStopTruncated { .. }
+            | Self::ObsoleteCommand { .. }
+            | Self::MalformedUrl { .. }
+            | Self::MalformedEmail { .. }
+            | Self::HighPlayLevel { .. }
+            | Self::TrailingWhitespace { .. }
+            | Self::FullWidthDigits { .. }
+            | Self::RankAndDefexrank { .. }
+            | Self::DuplicateSubtitle { .. }
+            | Self::ShortIdentifier { .. } => Severity::Info,
+            Self::InvalidValue { .. }
+            | Self::NegativeStop { .. }
+            | Self::UnknownBgaLayer { .. }
+            | Self::UnknownPlayOption { .. }
+            | Self::ScientificNotation { .. }
+            | Self::DecimalComma { .. }
+            | Self::BaseAfterDefinitions => Severity::Warning,
+            Self::PlayerSideMismatch { .. }
+            | Self::InconsistentSubdivision { .. }
+            | Self::TruncatedInput { .. } => Severity::Error,
+        }
+    }
+}
+
+/// The worst severity among `warnings`, or `None` if there aren't any.
+///
+/// Handy for deciding whether to reject a chart, as in
+/// `max_severity(&warnings) >= Some(Severity::Error)`.
+pub fn max_severity(warnings: &[ParseWarning]) -> Option<Severity> {
+    warnings.iter().map(ParseWarning::severity).max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn severities() {
+        let id = ObjectId::ZERO;
+        let stop = ParseWarning::FractionalStopTruncated {
+            id,
+            value: "1.5".to_string(),
+        };
+        let option = ParseWarning::UnknownPlayOption {
+            option: "FLIP".to_string(),
+        };
+        let truncated = ParseWarning::TruncatedInput {
+            line: "#WAV".to_string(),
+        };
+        assert_eq!(stop.severity(), Severity::Info);
+        assert_eq!(option.severity(), Severity::Warning);
+        assert_eq!(truncated.severity(), Severity::Error);
+        assert!(Severity::Info < Severity::Warning && Severity::Warning < Severity::Error);
+
+        assert_eq!(max_severity(&[]), None);
+        assert_eq!(
+            max_severity(core::slice::from_ref(&stop)),
+            Some(Severity::Info)
+        );
+        assert_eq!(
+            max_severity(&[option, truncated, stop]),
+            Some(Severity::Error)
+        );
+    }
+}
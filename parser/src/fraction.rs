@@ -0,0 +1,121 @@
+use core::cmp::Ordering;
+use core::fmt;
+use core::ops::Add;
+
+/// An exact position within a measure, as a fraction of the measure.
+///
+/// Channel data divides a measure into equal slots, so every object position
+/// is a rational number. Keeping it that way means a third plus a third plus a
+/// third is exactly one measure, where summing `f64`s slowly drifts. Convert
+/// with [`Fraction::to_f64`] only once you need a time.
+///
+/// Fractions are always kept in lowest terms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fraction {
+    num: u64,
+    den: u64,
+}
+
+impl Fraction {
+    pub const ZERO: Fraction = Fraction { num: 0, den: 1 };
+    pub const ONE: Fraction = Fraction { num: 1, den: 1 };
+
+    /// `num / den` in lowest terms.
+    ///
+    /// # Panics
+    /// If `den` is 0.
+    pub fn new(num: u64, den: u64) -> Self {
+        assert!(den != 0, "fraction with a zero denominator");
+        let divisor = gcd(num, den);
+        Self {
+            num: num / divisor,
+            den: den / divisor,
+        }
+    }
+
+    pub fn num(&self) -> u64 {
+        self.num
+    }
+
+    pub fn den(&self) -> u64 {
+        self.den
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+}
+
+impl Add for Fraction {
+    type Output = Fraction;
+
+    fn add(self, other: Fraction) -> Fraction {
+        let den = lcm(self.den, other.den);
+        Fraction::new(
+            self.num * (den / self.den) + other.num * (den / other.den),
+            den,
+        )
+    }
+}
+
+impl Ord for Fraction {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.num as u128 * other.den as u128).cmp(&(other.num as u128 * self.den as u128))
+    }
+}
+
+impl PartialOrd for Fraction {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Fraction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.num, self.den)
+    }
+}
+
+pub(crate) fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+pub(crate) fn lcm(a: u64, b: u64) -> u64 {
+    a / gcd(a, b) * b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn thirds_sum_to_a_measure() {
+        let third = Fraction::new(1, 3);
+        assert_eq!(third + third + third, Fraction::ONE);
+    }
+
+    #[test]
+    fn lowest_terms() {
+        let half = Fraction::new(96, 192);
+        assert_eq!((half.num(), half.den()), (1, 2));
+        assert_eq!(Fraction::new(0, 7), Fraction::ZERO);
+        assert_eq!(half.to_string(), "1/2");
+    }
+
+    #[test]
+    fn ordering() {
+        assert!(Fraction::new(1, 3) < Fraction::new(1, 2));
+        assert!(Fraction::new(2, 3) > Fraction::new(5, 8));
+        assert_eq!(Fraction::new(3, 4).to_f64(), 0.75);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_denominator() {
+        Fraction::new(1, 0);
+    }
+}
@@ -1,67 +1,551 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
 use strum_macros::FromRepr;
 
+use crate::error::{ParseError, ParseWarning};
+use crate::id::ObjectId;
+
+/// The header commands of a chart.
+///
+/// `Header::default()` applies the documented default of every command, so a
+/// parser can start from it and overlay whatever the chart specifies.
+/// Omissible commands without a sensible default are `None`.
+///
+/// Definitions (`#WAVxx` and friends) are kept in the order they appear,
+/// redefinitions included. Lookups by id take the last definition.
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct Header {
-    player: Player,
+    pub(crate) player: Player,
+    pub(crate) base: Base,
+    pub(crate) rank: Rank,
+    pub(crate) defexrank: Option<DefExRank>,
+    pub(crate) total: Total,
+    pub(crate) volwav: Volwav,
+    pub(crate) stagefile: Option<Stagefile>,
+    pub(crate) banner: Option<Banner>,
+    pub(crate) backbmp: Option<BackBmp>,
+    pub(crate) playlevel: PlayLevel,
+    pub(crate) difficulty: Option<Difficulty>,
+    pub(crate) title: Option<Title>,
+    pub(crate) subtitle: Option<Subtitle>,
+    pub(crate) artist: Option<Artist>,
+    pub(crate) subartist: Option<Subartist>,
+    pub(crate) maker: Option<Maker>,
+    pub(crate) genre: Genre,
+    pub(crate) url: Option<Url>,
+    pub(crate) email: Option<Email>,
+    pub(crate) bpm: ConstantBPM,
+    pub(crate) lntype: Option<LNType>,
+    pub(crate) lnobj_ids: BTreeSet<ObjectId>,
+    pub(crate) ln_mode: Option<LnMode>,
+    pub(crate) divideprop: Option<DivideProp>,
+    pub(crate) extchr: Vec<String>,
+    pub(crate) wav_path: Option<PathWav>,
+    pub(crate) options: Vec<PlayOption>,
+    pub(crate) wavs: Vec<Wav>,
+    pub(crate) bmps: Vec<Bmp>,
+    pub(crate) exbmps: Vec<ExBmp>,
+    /// Every `#BMPxx` and `#EXBMPxx` in the order they appear, as indices into
+    /// `bmps` and `exbmps`, since they share ids and the last one wins.
+    pub(crate) images: Vec<ImageIndex>,
+    pub(crate) argbs: Vec<Argb>,
+    pub(crate) exbpms: Vec<ExBPM>,
+    pub(crate) stops: Vec<Stop>,
+}
+
+impl Header {
+    pub fn player(&self) -> &Player {
+        &self.player
+    }
+
+    pub fn base(&self) -> &Base {
+        &self.base
+    }
+
+    pub fn rank(&self) -> &Rank {
+        &self.rank
+    }
+
+    pub fn defexrank(&self) -> Option<f32> {
+        self.defexrank.as_ref().map(|DefExRank(percent)| *percent)
+    }
+
+    /// The judge the chart is played with.
+    ///
+    /// `#DEFEXRANK` takes precedence over `#RANK` when a chart has both, as in
+    /// LR2 and beatoraja, wherever either appears in the file.
+    pub fn effective_judge(&self) -> JudgeRankType {
+        match &self.defexrank {
+            Some(DefExRank(percent)) => JudgeRankType::Defexrank(*percent),
+            None => JudgeRankType::Rank(self.rank.clone()),
+        }
+    }
+
+    pub fn total(&self) -> f64 {
+        self.total.0
+    }
+
+    pub fn volwav(&self) -> i32 {
+        self.volwav.0
+    }
+
+    pub fn stagefile(&self) -> Option<&str> {
+        self.stagefile.as_ref().map(|Stagefile(file)| file.as_str())
+    }
+
+    pub fn banner(&self) -> Option<&str> {
+        self.banner.as_ref().map(|Banner(file)| file.as_str())
+    }
+
+    pub fn backbmp(&self) -> Option<&str> {
+        self.backbmp.as_ref().map(|BackBmp(file)| file.as_str())
+    }
+
+    pub fn playlevel(&self) -> i32 {
+        self.playlevel.0
+    }
+
+    pub fn difficulty(&self) -> Option<&Difficulty> {
+        self.difficulty.as_ref()
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_ref().map(|Title(title)| title.as_str())
+    }
+
+    pub fn subtitle(&self) -> Option<&str> {
+        self.subtitle
+            .as_ref()
+            .map(|Subtitle(subtitle)| subtitle.as_str())
+    }
+
+    pub fn artist(&self) -> Option<&str> {
+        self.artist.as_ref().map(|Artist(artist)| artist.as_str())
+    }
+
+    pub fn subartist(&self) -> Option<&str> {
+        self.subartist
+            .as_ref()
+            .map(|Subartist(subartist)| subartist.as_str())
+    }
+
+    pub fn maker(&self) -> Option<&str> {
+        self.maker.as_ref().map(|Maker(maker)| maker.as_str())
+    }
+
+    pub fn genre(&self) -> &str {
+        &self.genre.0
+    }
+
+    pub fn url(&self) -> Option<&Url> {
+        self.url.as_ref()
+    }
+
+    pub fn email(&self) -> Option<&Email> {
+        self.email.as_ref()
+    }
+
+    /// The constant `#BPM`, before any BPM changes.
+    pub fn bpm(&self) -> f32 {
+        self.bpm.0
+    }
+
+    pub fn lntype(&self) -> Option<u8> {
+        self.lntype.as_ref().map(|LNType(lntype)| *lntype)
+    }
+
+    /// Every id given by an `#LNOBJ`. Any of them ends a long note.
+    pub fn lnobj_ids(&self) -> &BTreeSet<ObjectId> {
+        &self.lnobj_ids
+    }
+
+    /// Whether `id` was given by an `#LNOBJ`.
+    pub fn is_lnobj(&self, id: ObjectId) -> bool {
+        self.lnobj_ids.contains(&id)
+    }
+
+    /// The `#LNMODE` of the chart, or `None` to use the player's preference.
+    pub fn ln_mode(&self) -> Option<LnMode> {
+        self.ln_mode
+    }
+
+    /// The `#DIVIDEPROP` resolution hint, if the chart has one.
+    pub fn divideprop(&self) -> Option<u32> {
+        self.divideprop
+            .as_ref()
+            .map(|DivideProp(divisions)| *divisions)
+    }
+
+    /// The play options forced by `#OPTION`, in the order given.
+    pub fn options(&self) -> &[PlayOption] {
+        &self.options
+    }
+
+    /// The arguments of every `#ExtChr`, verbatim and in order.
+    ///
+    /// BM98 era charts use `#ExtChr` to animate the dancing character, with a
+    /// sprite layout that varies between players. Nothing modern supports it,
+    /// so we don't try to make sense of it, only keep it so that editing a
+    /// chart doesn't lose it.
+    pub fn extchr(&self) -> &[String] {
+        &self.extchr
+    }
+
+    pub fn wavs(&self) -> &[Wav] {
+        &self.wavs
+    }
+
+    /// The `#WAVxx` in effect for `id`.
+    ///
+    /// A redefinition applies to the whole chart, not only from where it
+    /// appears, so this is the last definition in the file.
+    pub fn wav(&self, id: ObjectId) -> Option<&Wav> {
+        self.wavs.iter().rev().find(|wav| wav.id() == id)
+    }
+
+    /// Every `#WAVxx` for `id`, in the order they appear, for spotting a
+    /// keysound that was accidentally overridden. Only the last one is used.
+    pub fn wav_history(&self, id: ObjectId) -> Vec<&Wav> {
+        self.wavs.iter().filter(|wav| wav.id() == id).collect()
+    }
+
+    /// The `#PATH_WAV` directory, always ending in a `/`.
+    pub fn wav_path(&self) -> Option<&str> {
+        self.wav_path.as_ref().map(|PathWav(path)| path.as_str())
+    }
+
+    /// Where to look for the `#WAVxx` in effect for `id`, relative to the
+    /// chart, with `#PATH_WAV` prepended if there is one.
+    pub fn wav_file(&self, id: ObjectId) -> Option<String> {
+        let wav = self.wav(id)?;
+        Some(format!("{}{}", self.wav_path().unwrap_or(""), wav.path()))
+    }
+
+    pub fn bmps(&self) -> &[Bmp] {
+        &self.bmps
+    }
+
+    /// The `#BMPxx` in effect for `id`.
+    pub fn bmp(&self, id: ObjectId) -> Option<&Bmp> {
+        self.bmps.iter().rev().find(|bmp| bmp.id() == id)
+    }
+
+    pub fn exbmps(&self) -> &[ExBmp] {
+        &self.exbmps
+    }
+
+    /// The `#BMPxx` or `#EXBMPxx` in effect for `id`, which is what BGA
+    /// channel data shows.
+    pub fn bga_image(&self, id: ObjectId) -> Option<BgaImage<'_>> {
+        self.images
+            .iter()
+            .rev()
+            .map(|index| match *index {
+                ImageIndex::Bmp(i) => BgaImage::Bmp(&self.bmps[i]),
+                ImageIndex::ExBmp(i) => BgaImage::ExBmp(&self.exbmps[i]),
+            })
+            .find(|image| image.id() == id)
+    }
+
+    pub fn argbs(&self) -> &[Argb] {
+        &self.argbs
+    }
+
+    pub fn exbpms(&self) -> &[ExBPM] {
+        &self.exbpms
+    }
+
+    /// The `#BPMxx` or `#EXBPMxx` in effect for `id`.
+    pub fn exbpm(&self, id: ObjectId) -> Option<&ExBPM> {
+        self.exbpms.iter().rev().find(|exbpm| exbpm.id() == id)
+    }
+
+    pub fn stops(&self) -> &[Stop] {
+        &self.stops
+    }
+
+    /// The `#STOPxx` in effect for `id`.
+    pub fn stop(&self, id: ObjectId) -> Option<&Stop> {
+        self.stops.iter().rev().find(|stop| stop.id() == id)
+    }
+
+    /// Guesses the difficulty of a chart that has no `#DIFFICULTY`.
+    ///
+    /// A difficulty tag at the end of the chart's `filename` is the best hint,
+    /// failing that we go by `#PLAYLEVEL`. This never looks at `#DIFFICULTY`
+    /// itself, so callers can tell a declared difficulty from a guessed one.
+    ///
+    /// Returns `None` when neither gives anything to go on.
+    pub fn infer_difficulty(&self, filename: Option<&str>) -> Option<Difficulty> {
+        filename
+            .and_then(Difficulty::from_filename)
+            .or_else(|| Difficulty::from_playlevel(self.playlevel.0))
+    }
+
+    /// Every metadata field the chart gives, as text, keyed by the lowercase
+    /// command name.
+    ///
+    /// For UIs that list metadata generically. Omitted fields without a
+    /// default are left out, as is an empty `#GENRE`. Enumerated commands are
+    /// given as the number the chart would write, so `#RANK 2` is `"2"`.
+    /// Definitions like `#WAVxx` aren't metadata, and aren't included. Nor
+    /// are the commands that can repeat, `#OPTION` and `#ExtChr`.
+    pub fn metadata_map(&self) -> BTreeMap<String, String> {
+        let mut map = BTreeMap::new();
+        let mut insert = |key: &str, value: Option<String>| {
+            if let Some(value) = value {
+                map.insert(key.to_string(), value);
+            }
+        };
+
+        insert("title", self.title().map(str::to_string));
+        insert("subtitle", self.subtitle().map(str::to_string));
+        insert("artist", self.artist().map(str::to_string));
+        insert("subartist", self.subartist().map(str::to_string));
+        insert("maker", self.maker().map(str::to_string));
+        insert(
+            "genre",
+            Some(self.genre().to_string()).filter(|genre| !genre.is_empty()),
+        );
+        insert("url", self.url().map(|url| url.as_str().to_string()));
+        insert(
+            "email",
+            self.email().map(|email| email.as_str().to_string()),
+        );
+        insert("stagefile", self.stagefile().map(str::to_string));
+        insert("banner", self.banner().map(str::to_string));
+        insert("backbmp", self.backbmp().map(str::to_string));
+        insert("player", Some((self.player.clone() as u8 + 1).to_string()));
+        insert("base", Some((self.base.clone() as u8).to_string()));
+        insert("rank", Some((self.rank.clone() as u8).to_string()));
+        insert("defexrank", self.defexrank().map(|rank| rank.to_string()));
+        insert("total", Some(self.total().to_string()));
+        insert("volwav", Some(self.volwav().to_string()));
+        insert("playlevel", Some(self.playlevel().to_string()));
+        insert(
+            "difficulty",
+            self.difficulty()
+                .map(|difficulty| (difficulty.clone() as u8 + 1).to_string()),
+        );
+        insert("bpm", Some(self.bpm().to_string()));
+        insert("lntype", self.lntype().map(|lntype| lntype.to_string()));
+        insert(
+            "lnmode",
+            self.ln_mode().map(|mode| (mode as u8).to_string()),
+        );
+        insert(
+            "divideprop",
+            self.divideprop().map(|divisions| divisions.to_string()),
+        );
+        insert("path_wav", self.wav_path().map(str::to_string));
+        map
+    }
+
+    /// The title folded into a key for matching the same song across charts.
+    ///
+    /// Full-width characters are folded to half-width, implicit subtitle
+    /// delimiters (`~`, `"`, brackets) are replaced by spaces, and runs of
+    /// whitespace are collapsed. `-` is left alone, as it's far more common
+    /// inside titles than as a delimiter.
+    ///
+    /// An omitted title normalizes to an empty string.
+    pub fn normalized_title(&self) -> String {
+        let Some(Title(title)) = &self.title else {
+            return String::new();
+        };
+
+        let mut normalized = String::with_capacity(title.len());
+        let folded = title.chars().map(fold_width).map(|c| match c {
+            '~' | '"' | '(' | ')' | '[' | ']' | '<' | '>' => ' ',
+            c => c,
+        });
+        for c in folded {
+            if c.is_whitespace() {
+                if !normalized.is_empty() && !normalized.ends_with(' ') {
+                    normalized.push(' ');
+                }
+            } else {
+                normalized.push(c);
+            }
+        }
+        normalized.truncate(normalized.trim_end().len());
+        normalized
+    }
+}
+
+/// A short summary for people, along the lines of
+///
+/// ```text
+/// Title
+/// artist: Artist
+/// genre: Genre
+/// bpm: 150, level: 12, total: 300
+/// ```
+///
+/// Anything omitted is shown as `-`, except the genre, which is just empty.
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.title().unwrap_or("-"))?;
+        writeln!(f, "artist: {}", self.artist().unwrap_or("-"))?;
+        writeln!(f, "genre: {}", self.genre())?;
+        write!(
+            f,
+            "bpm: {}, level: {}, total: {}",
+            self.bpm(),
+            self.playlevel(),
+            self.total()
+        )
+    }
+}
+
+/// Folds a full-width character to its half-width equivalent.
+///
+/// Japanese charts mix widths freely, so `（`, `～` and `Ａ` show up where
+/// `(`, `~` and `A` were meant. The wave dash `〜` is treated as a tilde too.
+pub(crate) fn fold_width(c: char) -> char {
+    match c {
+        '\u{3000}' => ' ',
+        '\u{301C}' => '~',
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        c => c,
+    }
 }
 
 /// `#PLAYER [1-4]`. Defines the play side.
-#[derive(FromRepr, Debug, PartialEq, Clone)]
+#[derive(FromRepr, Debug, PartialEq, Clone, Default)]
 #[repr(u8)]
 pub enum Player {
-    One,   // SP
+    #[default]
+    One, // SP
     Two,   // Couple play
     Three, // DP
     Four,  // Battle Play. This is very, very rare
 }
 
-impl Default for Player {
-    fn default() -> Self {
-        Self::One
+impl Player {
+    /// Parses `#PLAYER n`.
+    pub fn parse(value: &str) -> Result<Self, ParseError> {
+        parse_number::<u8>("PLAYER", value)?
+            .checked_sub(1)
+            .and_then(Self::from_repr)
+            .ok_or_else(|| invalid("PLAYER", value))
+    }
+}
+
+/// `#BASE [36|62]`. Numbering base for object identifiers.
+///
+/// Identifiers such as the `xx` in `#WAVxx` are normally two base-36 digits,
+/// which caps a chart at 1295 definitions per command.
+/// beatoraja added `#BASE 62`, where lowercase letters are distinct digits
+/// rather than aliases of their uppercase counterparts, raising the cap to 3843.
+///
+/// Omissible, and defaults to 36.
+///
+/// Under `#BASE 62`, `#WAVaa` and `#WAVAA` are two different sounds. See
+/// [`ObjectId`] for how the two bases line up.
+///
+/// A chart may only use one base, so `#BASE` is expected to come before any
+/// definitions. A `#BASE` after definitions gets a
+/// [`ParseWarning::BaseAfterDefinitions`].
+#[derive(FromRepr, Debug, PartialEq, Clone, Default)]
+#[repr(u8)]
+pub enum Base {
+    #[default]
+    Base36 = 36,
+    Base62 = 62,
+}
+
+impl Base {
+    /// Parses `#BASE n`.
+    pub fn parse(value: &str) -> Result<Self, ParseError> {
+        Self::from_repr(parse_number("BASE", value)?).ok_or_else(|| invalid("BASE", value))
     }
 }
 
 /// `#RANK [0-3]`. Defines the judge difficulty.
 ///
 /// We follow LR2 convention here, so Rank is 0,1,2,3
-#[derive(FromRepr, Debug, PartialEq, Clone)]
+#[derive(FromRepr, Debug, PartialEq, Clone, Default)]
 #[repr(u8)]
 pub enum Rank {
     VeryHard, // RANK 0, +-8ms
     Hard,     // RANK 1, +- 15ms
-    Normal,   // RANK 2, +- 18ms
-    Easy,     // RANK 3, +- 21ms
+    // LR2 Convention is to apply Normal when unspecified.
+    #[default]
+    Normal, // RANK 2, +- 18ms
+    Easy, // RANK 3, +- 21ms
 }
 
-// LR2 Convention is to apply Normal when unspecified.
-impl Default for Rank {
-    fn default() -> Self {
-        Self::Normal
+impl Rank {
+    /// Parses `#RANK n`.
+    pub fn parse(value: &str) -> Result<Self, ParseError> {
+        Self::from_repr(parse_number("RANK", value)?).ok_or_else(|| invalid("RANK", value))
+    }
+
+    /// The judge rank beatoraja stores for this `#RANK`.
+    ///
+    /// beatoraja measures every judge on the bmson scale, where a larger
+    /// number is a wider window. `#RANK` maps onto it as
+    ///
+    /// | `#RANK` | beatoraja |
+    /// |---------|-----------|
+    /// | 0       | 25        |
+    /// | 1       | 50        |
+    /// | 2       | 75        |
+    /// | 3       | 100       |
+    ///
+    /// so `#DEFEXRANK 100` and `#RANK 2` both come out as 75.
+    pub fn to_beatoraja(&self) -> i32 {
+        match self {
+            Self::VeryHard => 25,
+            Self::Hard => 50,
+            Self::Normal => 75,
+            Self::Easy => 100,
+        }
+    }
+
+    /// The `#RANK` for a beatoraja judge rank, if it's one of the four that
+    /// [`Rank::to_beatoraja`] produces.
+    pub fn from_beatoraja(judgerank: i32) -> Option<Self> {
+        match judgerank {
+            25 => Some(Self::VeryHard),
+            50 => Some(Self::Hard),
+            75 => Some(Self::Normal),
+            100 => Some(Self::Easy),
+            _ => None,
+        }
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum JudgeRankType {
     /// `#RANK [0-3]` Normal rank system.
     ///
     /// This is what you see 99% of the time.
-    Rank,
+    Rank(Rank),
 
     /// `#DEFEXRANK n`. Percentage judge.
     /// Defexrank is very strange. It specifies judge difficulty as a percentage of RANK 2.
     /// This means that 100 is equal to RANK 2. If a DefExRank of 199.97 is sepcified then
     /// the rank is 199.97% of RANK 2.
     ///
-    /// TODO: Implement Defexrank
+    /// Takes precedence over `#RANK`.
     Defexrank(f32),
     /// `#EXRANK[01-ZZ] n`. In-chart adjustable rank.
     /// Exrank is also weird. It allows the timing window of the chart to be changed
     /// during play.
     ///
-    /// The string represents the identifier (In the example "cc" or "aa")
+    /// The ObjectId represents the identifier (In the example "cc" or "aa")
     /// and the f32 represents the rank as a percentage of rank 2.
     ///
     /// # Example
     /// To steal from hitkey as an example.
-    /// ```
+    /// ```text
     /// #RANK 2
     /// #EXRANKaa 48
     /// #EXRANKcc 100
@@ -69,7 +553,20 @@ pub enum JudgeRankType {
     /// ```
     /// Would result in the timing window for measure 114 changing to DEFEXRANK 48,
     /// and then going back to DEFEXRANK 100 at the end of the measure.
-    Exrank(String, f32),
+    Exrank(ObjectId, f32),
+}
+
+/// `#DEFEXRANK n`. Judge as a percentage of `#RANK 2`.
+///
+/// See [`JudgeRankType::Defexrank`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefExRank(f32);
+
+impl DefExRank {
+    /// Parses `#DEFEXRANK n`.
+    pub fn parse(value: &str) -> Result<Self, ParseError> {
+        parse_float("DEFEXRANK", value).map(|percent| Self(percent as f32))
+    }
 }
 
 #[cfg_attr(doc, katexit::katexit)]
@@ -88,6 +585,7 @@ pub enum JudgeRankType {
 /// - LR2 uses 160
 /// - jbmsparser for beatoraja uses 100.
 /// - We will use 160
+#[derive(Debug, Clone, PartialEq)]
 pub struct Total(f64);
 
 impl Default for Total {
@@ -96,6 +594,40 @@ impl Default for Total {
     }
 }
 
+impl Total {
+    /// Parses `#TOTAL n`.
+    pub fn parse(value: &str) -> Result<Self, ParseError> {
+        parse_float("TOTAL", value).map(Self)
+    }
+
+    /// Gauge gained per PGREAT, for a chart with `object_count` objects to hit.
+    ///
+    /// A chart with no objects recovers nothing rather than dividing by zero.
+    pub fn pgreat_recovery(&self, object_count: usize) -> f64 {
+        if object_count == 0 {
+            return 0.0;
+        }
+        self.0 / object_count as f64
+    }
+
+    /// The total beatoraja stores for this `#TOTAL`.
+    ///
+    /// beatoraja keeps `#TOTAL` as written, so this is the value itself.
+    /// It differs from us on an omitted `#TOTAL`, which it derives from the
+    /// note count rather than defaulting to 160, so an omitted total doesn't
+    /// round-trip.
+    pub fn to_beatoraja(&self) -> f64 {
+        self.0
+    }
+
+    /// The `#TOTAL` for a beatoraja total.
+    ///
+    /// beatoraja treats a total of 0 or less as undefined, so those are `None`.
+    pub fn from_beatoraja(total: f64) -> Option<Self> {
+        (total.is_finite() && total > 0.0).then_some(Self(total))
+    }
+}
+
 /// `#VOLWAV n`. Flat volume multiplier.
 ///
 /// Defaults to 100.
@@ -105,23 +637,40 @@ impl Default for Total {
 /// #VOLWAV 250 would be playing at 250% volume.
 ///
 /// #VOLWAV 25 would be playing at 25% volume.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Volwav(i32);
 
+impl Default for Volwav {
+    fn default() -> Self {
+        Self(100)
+    }
+}
+
+impl Volwav {
+    /// Parses `#VOLWAV n`.
+    pub fn parse(value: &str) -> Result<Self, ParseError> {
+        parse_number("VOLWAV", value).map(Self)
+    }
+}
+
 /// `#STAGEFILE imagefilename`. Splash screen.
 ///
 /// This command is omissible. When omitted it is expected that the default splashscreen
 /// will be used.
-pub struct Stagefile(String);
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stagefile(pub(crate) String);
 
 /// `#BANNER imagefilename`. Song select banner image.
-pub struct Banner(String);
+#[derive(Debug, Clone, PartialEq)]
+pub struct Banner(pub(crate) String);
 
 /// `#BACKBMP imagefilename`. Static "movie" background.
 ///
 /// If we chose to follow the OverActive style, then this is a pre-movie splash
 /// like the song title, genre and such in IIDX.
 /// https://right-stick.sub.jp/backbmp/index.html
-pub struct BackBmp(String);
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackBmp(pub(crate) String);
 
 /// `#PLAYLEVEL n`. Song difficulty.
 ///
@@ -129,7 +678,10 @@ pub struct BackBmp(String);
 ///
 /// #PLAYLEVEL 0 is a strange case. This is usually for gimmick charts which
 /// use commands like `#RANDOM` or `#SWITCH`
-pub struct PlayLevel(u16);
+///
+/// Joke charts go negative too, as in `#PLAYLEVEL -1`, so it's signed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayLevel(i32);
 
 /// For whatever reason, BM98 used #PLAYLEVEL 3 as it's default if this was
 /// omitted. Apparently many followed this, even through it's not spec.
@@ -139,6 +691,13 @@ impl Default for PlayLevel {
     }
 }
 
+impl PlayLevel {
+    /// Parses `#PLAYLEVEL n`.
+    pub fn parse(value: &str) -> Result<Self, ParseError> {
+        parse_number("PLAYLEVEL", value).map(Self)
+    }
+}
+
 /// `#DIFFICULTY [1-5]`. Difficulty. Normal/Hyper etc
 ///
 /// We follow an adjusted IIDX naming convention in this enum.
@@ -149,7 +708,7 @@ impl Default for PlayLevel {
 /// unsortable and unfilterable by this metric.
 #[derive(FromRepr, Debug, PartialEq, Clone)]
 #[repr(u8)]
-enum Difficulty {
+pub enum Difficulty {
     Beginner, // Easy/Beginner/Light
     Normal,   // Normal/Standard
     Hyper,    // Hard
@@ -157,6 +716,56 @@ enum Difficulty {
     Insane,   // Kusofumen, 糞譜面, INSANE, 発狂, hakkyou, SUPER-CRAZY
 }
 
+impl Difficulty {
+    /// Parses `#DIFFICULTY n`.
+    pub fn parse(value: &str) -> Result<Self, ParseError> {
+        parse_number::<u8>("DIFFICULTY", value)?
+            .checked_sub(1)
+            .and_then(Self::from_repr)
+            .ok_or_else(|| invalid("DIFFICULTY", value))
+    }
+
+    /// Reads the difficulty tag chart filenames conventionally end in, like
+    /// the `ANOTHER` of `song_ANOTHER.bms` or the `7H` of `song_7H.bms`.
+    fn from_filename(filename: &str) -> Option<Self> {
+        let stem = filename.rsplit(['/', '\\']).next()?;
+        let stem = stem.rsplit_once('.').map_or(stem, |(stem, _)| stem);
+        let (_, tag) = stem.rsplit_once(['_', '-', ' '])?;
+        // Key count prefixes, as in `7A` or `14H`.
+        let tag = tag.trim_start_matches(|c: char| c.is_ascii_digit());
+
+        let is = |names: &[&str]| names.iter().any(|name| tag.eq_ignore_ascii_case(name));
+        if is(&["B", "BEGINNER", "EASY", "LIGHT"]) {
+            Some(Self::Beginner)
+        } else if is(&["N", "NORMAL", "STANDARD"]) {
+            Some(Self::Normal)
+        } else if is(&["H", "HYPER", "HARD"]) {
+            Some(Self::Hyper)
+        } else if is(&["A", "ANOTHER", "EX", "MAXIMUM"]) {
+            Some(Self::Another)
+        } else if is(&["X", "INSANE"]) {
+            Some(Self::Insane)
+        } else {
+            None
+        }
+    }
+
+    /// A rough difficulty for an IIDX style `#PLAYLEVEL`.
+    ///
+    /// Level 0 is usually a gimmick chart, and tells us nothing. Neither does
+    /// a negative one.
+    fn from_playlevel(level: i32) -> Option<Self> {
+        match level {
+            ..=0 => None,
+            1..=3 => Some(Self::Beginner),
+            4..=6 => Some(Self::Normal),
+            7..=9 => Some(Self::Hyper),
+            10..=12 => Some(Self::Another),
+            _ => Some(Self::Insane),
+        }
+    }
+}
+
 /// `#TITLE string` Title of the track.
 ///
 /// Unsurprisingly, defines the title of the track.
@@ -185,7 +794,8 @@ enum Difficulty {
 /// This is fine since there's now a `#SUBTITLE` command.
 ///
 /// We will support full width tilde and quote marks only.
-pub struct Title(String);
+#[derive(Debug, Clone, PartialEq)]
+pub struct Title(pub(crate) String);
 
 /// `#SUBTITLE string` Subtitle of the track
 ///
@@ -193,19 +803,26 @@ pub struct Title(String);
 /// defined.
 ///
 /// Omissible. LR2 will only check for a implicit subtitle if `#SUBTITLE` doesn't exist.
-pub struct Subtitle(String);
+///
+/// A few charts have more than one `#SUBTITLE`. LR2 keeps the last, like any
+/// other repeated command, so we do too rather than joining them, and warn
+/// about the one replaced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subtitle(pub(crate) String);
 
 /// `#ARTIST string`
 ///
 /// Definition of the track artist. Interestingly Artist isn't actually defined
 /// in the spec.
-pub struct Artist(String);
+#[derive(Debug, Clone, PartialEq)]
+pub struct Artist(pub(crate) String);
 
 /// `#SUBARTIST string`
 ///
 /// Added by LR2. This is used usually to define things like BGA artists,
 /// noters and other such co-artists.
-pub struct Subartist(String);
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subartist(pub(crate) String);
 
 /// `#MAKER string`
 ///
@@ -213,7 +830,8 @@ pub struct Subartist(String);
 ///
 /// Used to denote when a composer differs from the chart maker. In this case
 /// it is used to store the chart makers name.
-pub struct Maker(String);
+#[derive(Debug, Clone, PartialEq)]
+pub struct Maker(pub(crate) String);
 
 /// `#GENRE string`
 ///
@@ -221,7 +839,81 @@ pub struct Maker(String);
 /// By default it will be empty if not set.
 ///
 /// Supported by basically every client.
-pub struct Genre(String);
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Genre(pub(crate) String);
+
+/// `#URL string`
+///
+/// A link to the song or chart's page, usually the artist's site or an event
+/// entry.
+///
+/// Charts put all sorts in here, so the value is always kept as written.
+/// [`Url::is_valid`] says whether it looks like something safe to show as a
+/// link: an `http` or `https` URL with a host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Url(String);
+
+impl Url {
+    /// Parses `#URL string`, warning if it doesn't look like a URL.
+    pub fn parse(value: &str, warnings: &mut Vec<ParseWarning>) -> Self {
+        let url = Self(value.to_string());
+        if !url.is_valid() {
+            warnings.push(ParseWarning::MalformedUrl {
+                value: value.to_string(),
+            });
+        }
+        url
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let Some((scheme, rest)) = self.0.split_once("://") else {
+            return false;
+        };
+        let host = rest.split(['/', '?', '#']).next().unwrap_or("");
+        (scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https"))
+            && !host.is_empty()
+            && !self.0.contains(char::is_whitespace)
+    }
+}
+
+/// `#EMAIL string`
+///
+/// The contact address of the chart's author. Kept as written, like `#URL`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Email(String);
+
+impl Email {
+    /// Parses `#EMAIL string`, warning if it doesn't look like an address.
+    pub fn parse(value: &str, warnings: &mut Vec<ParseWarning>) -> Self {
+        let email = Self(value.to_string());
+        if !email.is_valid() {
+            warnings.push(ParseWarning::MalformedEmail {
+                value: value.to_string(),
+            });
+        }
+        email
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether this is a `local@domain.tld`, without checking any further.
+    pub fn is_valid(&self) -> bool {
+        let Some((local, domain)) = self.0.split_once('@') else {
+            return false;
+        };
+        !local.is_empty()
+            && !domain.contains('@')
+            && domain.split('.').count() >= 2
+            && domain.split('.').all(|label| !label.is_empty())
+            && !self.0.contains(char::is_whitespace)
+    }
+}
 
 // TODO: Landmine
 // It's in WAV00
@@ -233,6 +925,7 @@ pub struct Genre(String);
 ///
 /// it is expected that fractional BPMs are supported, as such we will repr
 /// this as a float.
+#[derive(Debug, Clone, PartialEq)]
 pub struct ConstantBPM(f32);
 
 // Standard defined default.
@@ -243,6 +936,13 @@ impl Default for ConstantBPM {
     }
 }
 
+impl ConstantBPM {
+    /// Parses `#BPM n`.
+    pub fn parse(value: &str) -> Result<Self, ParseError> {
+        parse_float("BPM", value).map(|bpm| Self(bpm as f32))
+    }
+}
+
 /// `#BPMxx n` OR `#EXBPM[01-ZZ] n`
 ///
 /// Hitkey refers to this as exBPM or "Extended BPM Change Command".
@@ -254,10 +954,10 @@ impl Default for ConstantBPM {
 ///
 /// For more info, see https://hitkey.nekokan.dyndns.info/exbpm-object.htm
 ///
-/// In parsing, we expect to parse the identifier to the string, and the bpm to the float.
+/// In parsing, we expect to parse the identifier to the ObjectId, and the bpm to the float.
 ///
 /// # Example
-/// ```
+/// ```text
 /// #BPMAA 256
 /// #BPMBB 155.5
 /// #00108:AABBAABB
@@ -265,9 +965,26 @@ impl Default for ConstantBPM {
 ///
 /// This defines the BPM AA to be 256, the BPM BB to be 155.5 and says where to use it
 /// in the chart itself.
-pub struct ExBPM(String, f32);
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExBPM(ObjectId, f32);
+
+impl ExBPM {
+    /// Parses the BPM of `#BPM<id> <value>`.
+    pub fn parse(id: ObjectId, value: &str) -> Result<Self, ParseError> {
+        parse_float(&format!("BPM{id}"), value).map(|bpm| Self(id, bpm as f32))
+    }
+
+    pub fn id(&self) -> ObjectId {
+        self.0
+    }
+
+    pub fn bpm(&self) -> f32 {
+        self.1
+    }
+}
 
 /// Represent the multiple types of BPM as enum variants.
+#[derive(Debug, Clone, PartialEq)]
 pub enum BPM {
     Constant(ConstantBPM),
     Extended(ExBPM),
@@ -295,7 +1012,7 @@ pub enum BPM {
 ///
 /// # Example
 ///
-/// ```
+/// ```text
 /// #STOP11 96
 /// #STOP22 96
 /// #00109:0011
@@ -313,7 +1030,7 @@ pub enum BPM {
 /// #00209:0022 says to do STOP 22 on measure 2
 ///
 /// An example of a 1 second stop
-/// ```
+/// ```text
 /// #BPM 60 // Set BPM to 60
 /// #STOP33 48 // 48 * 1/192 stop length
 /// #00109:33
@@ -324,7 +1041,64 @@ pub enum BPM {
 ///
 /// For some more examples, please refer to
 /// https://hitkey.bms.ms/cmds.htm#STOP
-pub struct Stop(String, u32);
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stop(ObjectId, u32);
+
+impl Stop {
+    /// Parses the duration of `#STOP<id> <value>`.
+    ///
+    /// Fractional durations are truncated and negative durations become 0,
+    /// each with a warning, since neither is something a player can act on.
+    /// A duration too long to hold is an invalid value.
+    pub fn parse(
+        id: ObjectId,
+        value: &str,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<Self, ParseError> {
+        let command = format!("STOP{id}");
+        let duration = parse_float(&command, value)?;
+
+        if duration < 0.0 {
+            warnings.push(ParseWarning::NegativeStop {
+                id,
+                value: value.to_string(),
+            });
+            return Ok(Self(id, 0));
+        }
+
+        if duration >= u32::MAX as f64 + 1.0 {
+            return Err(invalid(&command, value));
+        }
+        let truncated = duration as u32;
+        if truncated as f64 != duration {
+            warnings.push(ParseWarning::FractionalStopTruncated {
+                id,
+                value: value.to_string(),
+            });
+        }
+        Ok(Self(id, truncated))
+    }
+
+    pub fn id(&self) -> ObjectId {
+        self.0
+    }
+
+    /// The length of the stop, in 192nds of a 4/4 measure.
+    pub fn duration(&self) -> u32 {
+        self.1
+    }
+
+    /// How long the stop lasts, in seconds, at `bpm`, the BPM in effect
+    /// where the stop is placed.
+    ///
+    /// The 192nds are always of a 4/4 measure, whatever `#xxx02` says about
+    /// the measure the stop is in. A `#STOPxx 48` in a half length measure
+    /// still stops for a whole beat.
+    pub fn seconds(&self, bpm: f64) -> f64 {
+        // 192 parts of a measure is 48 per beat.
+        self.1 as f64 / 48.0 * 60.0 / bpm
+    }
+}
 
 /// `#LNTYPE[0-3]`. Long Note type
 ///
@@ -335,14 +1109,189 @@ pub struct Stop(String, u32);
 /// tell the user we're ignoring their chart if it's LNType 2 or 3.
 ///
 /// This is omissble.
+#[derive(Debug, Clone, PartialEq)]
 pub struct LNType(u8);
 
+impl LNType {
+    /// Parses `#LNTYPE n`.
+    pub fn parse(value: &str) -> Result<Self, ParseError> {
+        parse_number("LNTYPE", value).map(Self)
+    }
+}
+
 /// `#LNOBJ xx`
 ///
 /// This is RDM type LNs. They have sounds on keyup and they're annoying.
 ///
+/// A chart can have more than one `#LNOBJ`, for different terminating sounds.
+/// Unlike most commands a later one doesn't replace an earlier one, they all
+/// count.
+///
 /// TODO: Explain how this works better rather than handwaving it.
-pub struct LNObj(u32);
+#[derive(Debug, Clone, PartialEq)]
+pub struct LNObj(ObjectId);
+
+impl LNObj {
+    /// Parses `#LNOBJ xx`, with the id in the given base.
+    pub fn parse(value: &str, base: &Base) -> Result<Self, ParseError> {
+        ObjectId::parse(value.trim(), base)
+            .map(Self)
+            .ok_or_else(|| invalid("LNOBJ", value))
+    }
+
+    pub fn id(&self) -> ObjectId {
+        self.0
+    }
+}
+
+/// `#LNMODE [1-3]`. How long notes are judged.
+///
+/// Added by beatoraja, and quite common in newer charts.
+///
+/// - LN only judges the head. Releasing at any point after that is fine.
+/// - CN (charge note) judges the head and the release, and releasing early
+///   is a miss.
+/// - HCN (hell charge note) judges like CN, and additionally drains the gauge
+///   for as long as the note isn't held.
+///
+/// Omissible. When omitted, beatoraja applies whichever mode the player has
+/// configured, so we leave it unset rather than picking one.
+#[derive(FromRepr, Debug, PartialEq, Clone, Copy)]
+#[repr(u8)]
+pub enum LnMode {
+    Ln = 1,
+    Cn = 2,
+    Hcn = 3,
+}
+
+impl LnMode {
+    /// Parses `#LNMODE n`.
+    pub fn parse(value: &str) -> Result<Self, ParseError> {
+        Self::from_repr(parse_number("LNMODE", value)?).ok_or_else(|| invalid("LNMODE", value))
+    }
+}
+
+/// `#DIVIDEPROP n`. Measure resolution hint.
+///
+/// An obsolete command from early editors, giving the number of divisions per
+/// measure the chart was written at. Players ignore it, since channel data
+/// says how each measure is divided anyway, but it's a useful hint for very
+/// old charts.
+///
+/// Omissible.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DivideProp(u32);
+
+impl DivideProp {
+    /// Parses `#DIVIDEPROP n`.
+    pub fn parse(value: &str) -> Result<Self, ParseError> {
+        parse_number("DIVIDEPROP", value).map(Self)
+    }
+}
+
+/// `#OPTION name...`. Play options the chart forces on.
+///
+/// Used by gimmick charts that only work with a particular lane arrangement
+/// or cover, such as a chart drawn to be read in mirror. One `#OPTION` can
+/// name several options, separated by whitespace, and names are case
+/// insensitive. Spellings vary between players, so we take the common ones.
+///
+/// The timed counterpart, `#CHANGEOPTIONxx` placed on channel `A6`, isn't
+/// handled yet.
+///
+/// # Example
+/// ```text
+/// #OPTION MIRROR SUDDEN
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayOption {
+    Mirror,
+    Random,
+    SRandom,     // Super random, every note on its own lane
+    HRandom,     // Random that avoids jacks
+    AllScratch,  // Moves as many notes as it can onto the scratch
+    Sudden,      // Lane cover from the top
+    Hidden,      // Lane cover from the bottom
+    HiddenPlus,  // Cover over the judge line only
+    SuddenPlus,  // Adjustable lane cover from the top
+    AutoScratch, // Scratch is played for you
+}
+
+impl PlayOption {
+    /// Parses the names of `#OPTION`.
+    ///
+    /// A name we don't know is ignored with a warning, so this never fails.
+    pub fn parse(value: &str, warnings: &mut Vec<ParseWarning>) -> Vec<Self> {
+        value
+            .split_ascii_whitespace()
+            .filter_map(|name| {
+                let option = Self::from_name(name);
+                if option.is_none() {
+                    warnings.push(ParseWarning::UnknownPlayOption {
+                        option: name.to_string(),
+                    });
+                }
+                option
+            })
+            .collect()
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        let is = |names: &[&str]| names.iter().any(|n| name.eq_ignore_ascii_case(n));
+        if is(&["MIRROR"]) {
+            Some(Self::Mirror)
+        } else if is(&["RANDOM", "RAN"]) {
+            Some(Self::Random)
+        } else if is(&["S-RANDOM", "SRANDOM", "S-RAN"]) {
+            Some(Self::SRandom)
+        } else if is(&["H-RANDOM", "HRANDOM", "H-RAN"]) {
+            Some(Self::HRandom)
+        } else if is(&["ALLSCR", "ALL-SCRATCH", "ALLSCRATCH"]) {
+            Some(Self::AllScratch)
+        } else if is(&["SUDDEN"]) {
+            Some(Self::Sudden)
+        } else if is(&["HIDDEN"]) {
+            Some(Self::Hidden)
+        } else if is(&["HIDDEN+", "HIDDENPLUS"]) {
+            Some(Self::HiddenPlus)
+        } else if is(&["SUDDEN+", "SUDDENPLUS"]) {
+            Some(Self::SuddenPlus)
+        } else if is(&["AUTOSCR", "AUTO-SCRATCH", "AUTOSCRATCH"]) {
+            Some(Self::AutoScratch)
+        } else {
+            None
+        }
+    }
+}
+
+/// `#PATH_WAV directory`. Where to find the `#WAV` files.
+///
+/// Prefixed onto every `#WAV` filename, for charts that keep their sounds in
+/// a subdirectory. It has no effect on `#BMP`.
+///
+/// We store it with `/` separators and exactly one trailing `/`, whether the
+/// chart wrote one, a `\`, or nothing, so it can be prepended to a
+/// [`Wav::path`] as is.
+///
+/// # Example
+/// ```text
+/// #PATH_WAV sounds/
+/// #WAV01 a.wav
+/// ```
+/// Plays `sounds/a.wav` for `01`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathWav(pub(crate) String);
+
+impl PathWav {
+    /// Parses `#PATH_WAV directory`.
+    pub fn parse(value: &str) -> Result<Self, ParseError> {
+        let directory = value.trim().trim_end_matches(['/', '\\']);
+        if directory.is_empty() {
+            return Err(invalid("PATH_WAV", value));
+        }
+        Ok(Self(format!("{}/", directory.replace('\\', "/"))))
+    }
+}
 
 /// `#WAV[00-ZZ] filename`
 ///
@@ -350,6 +1299,12 @@ pub struct LNObj(u32);
 /// play!
 ///
 /// A single file is assignable to two or more indexes. This is used for polyphony.
+/// Heavily keysounded charts do this dozens of times over, so when parsing a
+/// chart, ids sharing a filename share a single allocation of it.
+///
+/// Charts made on Windows often separate directories with `\`, as in
+/// `drums\kick.wav`. [`Wav::path`] has them as `/`, which works everywhere,
+/// while [`Wav::filename`] keeps what was written.
 ///
 /// "Alternate search" is expected now. If we cant find example.wav we should search
 /// for example.ogg, example.mp3 etc
@@ -368,7 +1323,35 @@ pub struct LNObj(u32);
 ///
 /// For more info see https://hitkey.bms.ms/cmds.htm#WAVXX
 /// as this is one of the most complex commands we encounter
-pub struct Wav(u32, String);
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wav(ObjectId, pub(crate) Arc<str>, pub(crate) Arc<str>);
+
+impl Wav {
+    /// Parses the filename of `#WAV<id> <value>`.
+    pub fn parse(id: ObjectId, value: &str) -> Result<Self, ParseError> {
+        let file = parse_filename(&format!("WAV{id}"), value)?;
+        let path = if file.contains('\\') {
+            Arc::from(file.replace('\\', "/"))
+        } else {
+            file.clone()
+        };
+        Ok(Self(id, file, path))
+    }
+
+    pub fn id(&self) -> ObjectId {
+        self.0
+    }
+
+    /// The filename exactly as the chart wrote it.
+    pub fn filename(&self) -> &str {
+        &self.1
+    }
+
+    /// The filename with `/` separators, to look the file up by.
+    pub fn path(&self) -> &str {
+        &self.2
+    }
+}
 
 /// `#BMP[00-ZZ] filename`
 ///
@@ -392,4 +1375,630 @@ pub struct Wav(u32, String);
 /// PNG, JPG, GIF, TGA, DDS are all common.
 ///
 /// Like with #WAV we should support alternate search. So try PNG then JPEG then GIF etc.
-pub struct Bmp(u32, String);
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bmp(ObjectId, pub(crate) Arc<str>);
+
+impl Bmp {
+    /// Parses the filename of `#BMP<id> <value>`.
+    pub fn parse(id: ObjectId, value: &str) -> Result<Self, ParseError> {
+        parse_filename(&format!("BMP{id}"), value).map(|file| Self(id, file))
+    }
+
+    pub fn id(&self) -> ObjectId {
+        self.0
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.1
+    }
+}
+
+/// `#EXBMP[00-ZZ] a,r,g,b filename`
+///
+/// A `#BMP` with a transparency key. Pixels matching the ARGB colour are
+/// treated as transparent when the image is used as a BGA layer.
+///
+/// Otherwise this behaves exactly like `#BMP`, and shares its id space, so an
+/// `#EXBMP01` is what channel data means by `01`.
+///
+/// # Example
+/// ```text
+/// #EXBMP01 255,0,0,0 layer.png
+/// ```
+/// Makes opaque black transparent in layer.png.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExBmp(ObjectId, [u8; 4], String);
+
+impl ExBmp {
+    /// Parses the `a,r,g,b filename` operand of `#EXBMP<id>`.
+    pub fn parse(id: ObjectId, value: &str) -> Result<Self, ParseError> {
+        let invalid = || ParseError::InvalidValue {
+            command: format!("EXBMP{id}"),
+            value: value.to_string(),
+        };
+
+        let (argb, filename) = value
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or_else(invalid)?;
+        let filename = filename.trim();
+        if filename.is_empty() {
+            return Err(invalid());
+        }
+
+        let argb = parse_argb(argb).ok_or_else(invalid)?;
+        Ok(Self(id, argb, filename.to_string()))
+    }
+
+    pub fn id(&self) -> ObjectId {
+        self.0
+    }
+
+    /// The transparency key, as `[a, r, g, b]`.
+    pub fn argb(&self) -> [u8; 4] {
+        self.1
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.2
+    }
+}
+
+/// Either kind of BGA image definition, as they share an id space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BgaImage<'h> {
+    Bmp(&'h Bmp),
+    ExBmp(&'h ExBmp),
+}
+
+impl BgaImage<'_> {
+    pub fn id(&self) -> ObjectId {
+        match self {
+            Self::Bmp(bmp) => bmp.id(),
+            Self::ExBmp(exbmp) => exbmp.id(),
+        }
+    }
+
+    pub fn filename(&self) -> &str {
+        match self {
+            Self::Bmp(bmp) => bmp.filename(),
+            Self::ExBmp(exbmp) => exbmp.filename(),
+        }
+    }
+}
+
+/// Where a BGA image definition is kept, for keeping both kinds in order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ImageIndex {
+    Bmp(usize),
+    ExBmp(usize),
+}
+
+/// Parses an `a,r,g,b` colour, as used by `#EXBMP` and `#ARGB`.
+fn parse_argb(argb: &str) -> Option<[u8; 4]> {
+    let mut channels = argb.split(',').map(str::parse::<u8>);
+    let mut parsed = [0; 4];
+    for slot in &mut parsed {
+        *slot = channels.next()?.ok()?;
+    }
+    match channels.next() {
+        Some(_) => None,
+        None => Some(parsed),
+    }
+}
+
+/// The BGA layers that `#ARGB` can target, by the letter that names them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BgaLayer {
+    Base,   // A, #xxx04
+    Layer,  // B, #xxx07
+    Layer2, // C, #xxx0A
+    Poor,   // D, #xxx06
+}
+
+/// `#ARGB[A-D] a,r,g,b`. Layer blending.
+///
+/// Multiplies a whole BGA layer by an ARGB colour. Alpha makes the layer
+/// translucent, and the colour channels tint it.
+///
+/// This composes with `#EXBMP`. The transparency key of an `#EXBMP` is
+/// applied to the image first, cutting out the keyed pixels, and then
+/// whatever is left is blended with the `#ARGB` of the layer it's shown on.
+///
+/// # Example
+/// ```text
+/// #ARGBB 128,255,255,255
+/// ```
+/// Draws the BGA layer (`#xxx07`) at half opacity.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Argb(BgaLayer, [u8; 4]);
+
+impl Argb {
+    /// Parses `#ARGB<layer> <value>`.
+    ///
+    /// A layer letter other than `A`-`D` doesn't name a layer, so the command
+    /// is ignored with a warning and `None` is returned.
+    pub fn parse(
+        layer: &str,
+        value: &str,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<Option<Self>, ParseError> {
+        let bga_layer = match layer.to_ascii_uppercase().as_str() {
+            "A" => BgaLayer::Base,
+            "B" => BgaLayer::Layer,
+            "C" => BgaLayer::Layer2,
+            "D" => BgaLayer::Poor,
+            _ => {
+                warnings.push(ParseWarning::UnknownBgaLayer {
+                    layer: layer.to_string(),
+                });
+                return Ok(None);
+            }
+        };
+
+        let argb = parse_argb(value.trim()).ok_or_else(|| ParseError::InvalidValue {
+            command: format!("ARGB{layer}"),
+            value: value.to_string(),
+        })?;
+        Ok(Some(Self(bga_layer, argb)))
+    }
+
+    pub fn layer(&self) -> BgaLayer {
+        self.0
+    }
+
+    /// The blend colour, as `[a, r, g, b]`.
+    pub fn argb(&self) -> [u8; 4] {
+        self.1
+    }
+}
+
+fn invalid(command: &str, value: &str) -> ParseError {
+    ParseError::InvalidValue {
+        command: command.to_string(),
+        value: value.to_string(),
+    }
+}
+
+fn parse_number<T: FromStr>(command: &str, value: &str) -> Result<T, ParseError> {
+    value.trim().parse().map_err(|_| invalid(command, value))
+}
+
+/// Like [`parse_number`], but rejects the `inf` and `NaN` that Rust would
+/// happily accept.
+fn parse_float(command: &str, value: &str) -> Result<f64, ParseError> {
+    parse_number::<f64>(command, value)
+        .ok()
+        .filter(|float| float.is_finite())
+        .ok_or_else(|| invalid(command, value))
+}
+
+fn parse_filename(command: &str, value: &str) -> Result<Arc<str>, ParseError> {
+    match value.trim() {
+        "" => Err(invalid(command, value)),
+        file => Ok(Arc::from(file)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{HeaderScan, parse_header};
+
+    fn parse(input: &str) -> (Header, Vec<ParseWarning>) {
+        let mut warnings = Vec::new();
+        let header = parse_header(input, HeaderScan::WholeFile, &mut warnings).unwrap();
+        (header, warnings)
+    }
+
+    #[test]
+    fn metadata_map() {
+        let (header, _) = parse(
+            "\
+#TITLE song
+#ARTIST someone
+#BPM 145.5
+#GENRE
+#BASE 62
+#DIVIDEPROP 4
+#PATH_WAV sounds/
+#DIFFICULTY 3
+",
+        );
+        let map = header.metadata_map();
+        assert_eq!(map["title"], "song");
+        assert_eq!(map["artist"], "someone");
+        assert_eq!(map["bpm"], "145.5");
+        assert_eq!(map["base"], "62");
+        assert_eq!(map["divideprop"], "4");
+        assert_eq!(map["path_wav"], "sounds/");
+        assert_eq!(map["difficulty"], "3");
+        assert_eq!(map["player"], "1");
+        assert_eq!(map["rank"], "2");
+        assert!(!map.contains_key("genre"));
+        assert!(!map.contains_key("subartist"));
+    }
+
+    #[test]
+    fn pgreat_recovery() {
+        let total = Total::parse("200").unwrap();
+        assert_eq!(total.pgreat_recovery(400), 0.5);
+        assert_eq!(total.pgreat_recovery(0), 0.0);
+    }
+
+    #[test]
+    fn default_header() {
+        let header = Header::default();
+        assert_eq!(header.player(), &Player::One);
+        assert_eq!(header.base(), &Base::Base36);
+        assert_eq!(header.rank(), &Rank::Normal);
+        assert_eq!(header.defexrank(), None);
+        assert_eq!(header.total(), 160.0);
+        assert_eq!(header.volwav(), 100);
+        assert_eq!(header.playlevel(), 3);
+        assert_eq!(header.difficulty(), None);
+        assert_eq!(header.bpm(), 130.0);
+        assert_eq!(header.genre(), "");
+        assert_eq!(header.title(), None);
+        assert!(header.wavs().is_empty());
+    }
+
+    #[test]
+    fn clone_equals_original() {
+        let (header, _) = parse("#TITLE song\n#BPM 150\n#WAV01 a.wav\n#STOP01 48\n");
+        let cloned = header.clone();
+        assert_eq!(cloned, header);
+
+        let (other, _) = parse("#TITLE song\n#BPM 151\n#WAV01 a.wav\n#STOP01 48\n");
+        assert_ne!(other, header);
+    }
+
+    #[test]
+    fn normalized_title() {
+        let (header, _) = parse("#TITLE Ｓｏｎｇ　（ＡＮＯＴＨＥＲ）\n");
+        assert_eq!(header.normalized_title(), "Song ANOTHER");
+
+        let (header, _) = parse("#TITLE Song (ANOTHER)\n");
+        assert_eq!(header.normalized_title(), "Song ANOTHER");
+
+        let (header, _) = parse("#TITLE Song ～another mix～\n");
+        assert_eq!(header.normalized_title(), "Song another mix");
+
+        assert_eq!(Header::default().normalized_title(), "");
+    }
+
+    #[test]
+    fn stop_durations() {
+        let id = ObjectId::from_base36("01").unwrap();
+        let mut warnings = Vec::new();
+
+        let stop = Stop::parse(id, "96", &mut warnings).unwrap();
+        assert_eq!(stop.duration(), 96);
+        assert!(warnings.is_empty());
+
+        let stop = Stop::parse(id, "96.7", &mut warnings).unwrap();
+        assert_eq!(stop.duration(), 96);
+        assert_eq!(
+            warnings,
+            [ParseWarning::FractionalStopTruncated {
+                id,
+                value: "96.7".to_string()
+            }]
+        );
+
+        warnings.clear();
+        let stop = Stop::parse(id, "-48", &mut warnings).unwrap();
+        assert_eq!(stop.duration(), 0);
+        assert_eq!(
+            warnings,
+            [ParseWarning::NegativeStop {
+                id,
+                value: "-48".to_string()
+            }]
+        );
+
+        assert!(Stop::parse(id, "long", &mut warnings).is_err());
+
+        warnings.clear();
+        assert!(Stop::parse(id, "99999999999", &mut warnings).is_err());
+        assert!(warnings.is_empty());
+        let stop = Stop::parse(id, "4294967295.5", &mut warnings).unwrap();
+        assert_eq!(stop.duration(), u32::MAX);
+        assert_eq!(
+            warnings,
+            [ParseWarning::FractionalStopTruncated {
+                id,
+                value: "4294967295.5".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn exbmp() {
+        let id = ObjectId::from_base36("01").unwrap();
+        let exbmp = ExBmp::parse(id, "255,0,0,0 layer.png").unwrap();
+        assert_eq!(exbmp.id(), id);
+        assert_eq!(exbmp.argb(), [255, 0, 0, 0]);
+        assert_eq!(exbmp.filename(), "layer.png");
+
+        assert!(ExBmp::parse(id, "255,0,0 layer.png").is_err());
+        assert!(ExBmp::parse(id, "255,0,0,0").is_err());
+
+        let (header, _) = parse("#EXBMP01 255,0,0,0 layer.png\n");
+        assert_eq!(header.exbmps(), [exbmp]);
+    }
+
+    #[test]
+    fn bga_image_takes_the_last_definition() {
+        let id = ObjectId::from_base36("01").unwrap();
+        let (header, _) = parse("#BMP01 a.png\n#EXBMP01 0,0,0,0 b.png\n");
+        assert_eq!(header.bga_image(id).unwrap().filename(), "b.png");
+        assert_eq!(header.bmp(id).unwrap().filename(), "a.png");
+
+        let (header, _) = parse("#EXBMP01 0,0,0,0 a.png\n#BMP01 b.png\n");
+        assert!(
+            matches!(header.bga_image(id), Some(BgaImage::Bmp(bmp)) if bmp.filename() == "b.png")
+        );
+        assert_eq!(header.bga_image(ObjectId::ZERO), None);
+    }
+
+    #[test]
+    fn argb() {
+        let (header, warnings) = parse("#ARGBB 128,255,255,255\n#ARGBZ 1,2,3,4\n");
+        let [argb] = header.argbs() else {
+            panic!("{:?}", header.argbs());
+        };
+        assert_eq!(argb.layer(), BgaLayer::Layer);
+        assert_eq!(argb.argb(), [128, 255, 255, 255]);
+        assert_eq!(
+            warnings,
+            [ParseWarning::UnknownBgaLayer {
+                layer: "Z".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn ln_mode() {
+        for (value, mode) in [("1", LnMode::Ln), ("2", LnMode::Cn), ("3", LnMode::Hcn)] {
+            let (header, _) = parse(&format!("#LNMODE {value}\n"));
+            assert_eq!(header.ln_mode(), Some(mode));
+        }
+        assert_eq!(Header::default().ln_mode(), None);
+        assert!(LnMode::parse("0").is_err());
+        assert!(LnMode::parse("4").is_err());
+    }
+
+    #[test]
+    fn infer_difficulty() {
+        let (header, _) = parse("#PLAYLEVEL 8\n");
+        assert_eq!(header.infer_difficulty(None), Some(Difficulty::Hyper));
+        assert_eq!(
+            header.infer_difficulty(Some("songs/foo/foo_ANOTHER.bms")),
+            Some(Difficulty::Another)
+        );
+        assert_eq!(
+            header.infer_difficulty(Some("foo_7b.bme")),
+            Some(Difficulty::Beginner)
+        );
+        assert_eq!(
+            header.infer_difficulty(Some("foo.bms")),
+            Some(Difficulty::Hyper)
+        );
+
+        let (header, _) = parse("#PLAYLEVEL 0\n#DIFFICULTY 5\n");
+        assert_eq!(header.infer_difficulty(Some("foo.bms")), None);
+    }
+
+    #[test]
+    fn beatoraja_rank() {
+        for (rank, judgerank) in [
+            (Rank::VeryHard, 25),
+            (Rank::Hard, 50),
+            (Rank::Normal, 75),
+            (Rank::Easy, 100),
+        ] {
+            assert_eq!(rank.to_beatoraja(), judgerank);
+            assert_eq!(Rank::from_beatoraja(judgerank), Some(rank));
+        }
+        assert_eq!(Rank::from_beatoraja(60), None);
+    }
+
+    #[test]
+    fn beatoraja_total() {
+        let total = Total::parse("300").unwrap();
+        assert_eq!(total.to_beatoraja(), 300.0);
+        assert_eq!(Total::from_beatoraja(300.0), Some(total));
+        assert_eq!(Total::from_beatoraja(0.0), None);
+        assert_eq!(Total::from_beatoraja(f64::NAN), None);
+    }
+
+    #[test]
+    fn extchr_is_kept_verbatim() {
+        let (header, warnings) = parse("#ExtChr 0101 02 10 10 40 40 -1 -1\n#EXTCHR 0102 03\n");
+        assert_eq!(header.extchr(), ["0101 02 10 10 40 40 -1 -1", "0102 03"]);
+        assert_eq!(
+            warnings,
+            [
+                ParseWarning::ObsoleteCommand {
+                    command: "ExtChr".to_string()
+                },
+                ParseWarning::ObsoleteCommand {
+                    command: "ExtChr".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn divideprop() {
+        let (header, warnings) = parse("#DIVIDEPROP 192\n");
+        assert_eq!(header.divideprop(), Some(192));
+        assert_eq!(
+            warnings,
+            [ParseWarning::ObsoleteCommand {
+                command: "DIVIDEPROP".to_string()
+            }]
+        );
+        assert_eq!(Header::default().divideprop(), None);
+    }
+
+    #[test]
+    fn path_wav() {
+        let (header, _) = parse("#PATH_WAV sounds/\n#WAV01 a.wav\n#BMP01 a.bmp\n");
+        let id = ObjectId::from_base36("01").unwrap();
+        assert_eq!(header.wav_path(), Some("sounds/"));
+        assert_eq!(header.wav_file(id).as_deref(), Some("sounds/a.wav"));
+        assert_eq!(header.bmp(id).unwrap().filename(), "a.bmp");
+
+        for value in ["sounds", "sounds\\", "sounds//"] {
+            assert_eq!(PathWav::parse(value).unwrap().0, "sounds/");
+        }
+        assert_eq!(PathWav::parse("a\\b").unwrap().0, "a/b/");
+        assert!(PathWav::parse(" / ").is_err());
+    }
+
+    #[test]
+    fn play_options() {
+        let (header, warnings) = parse("#OPTION mirror SUDDEN+ FLIP\n#OPTION H-RAN\n");
+        assert_eq!(
+            header.options(),
+            [
+                PlayOption::Mirror,
+                PlayOption::SuddenPlus,
+                PlayOption::HRandom
+            ]
+        );
+        assert_eq!(
+            warnings,
+            [ParseWarning::UnknownPlayOption {
+                option: "FLIP".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn summary() {
+        let (header, _) = parse(
+            "#TITLE Song\n#ARTIST Artist\n#GENRE Genre\n#BPM 150\n#PLAYLEVEL 12\n#TOTAL 300\n",
+        );
+        assert_eq!(
+            header.to_string(),
+            "Song\nartist: Artist\ngenre: Genre\nbpm: 150, level: 12, total: 300"
+        );
+        assert_eq!(
+            Header::default().to_string(),
+            "-\nartist: -\ngenre: \nbpm: 130, level: 3, total: 160"
+        );
+    }
+
+    #[test]
+    fn url_and_email() {
+        let (header, warnings) =
+            parse("#URL https://example.com/bms?id=1\n#EMAIL someone@example.com\n");
+        assert!(warnings.is_empty());
+        assert!(header.url().unwrap().is_valid());
+        assert!(header.email().unwrap().is_valid());
+
+        let (header, warnings) = parse("#URL example.com\n#EMAIL someone@example\n");
+        assert_eq!(header.url().unwrap().as_str(), "example.com");
+        assert!(!header.url().unwrap().is_valid());
+        assert_eq!(header.email().unwrap().as_str(), "someone@example");
+        assert_eq!(
+            warnings,
+            [
+                ParseWarning::MalformedUrl {
+                    value: "example.com".to_string()
+                },
+                ParseWarning::MalformedEmail {
+                    value: "someone@example".to_string()
+                },
+            ]
+        );
+
+        let mut warnings = Vec::new();
+        for url in ["ftp://example.com", "https://", "https://exa mple.com"] {
+            assert!(!Url::parse(url, &mut warnings).is_valid(), "{url}");
+        }
+    }
+
+    #[test]
+    fn stop_seconds() {
+        // Where a stop lands in a measure shortened by #xxx02, and the time
+        // it lands at, need a timeline. That regression test is deferred
+        // until there is one; this only covers the length.
+        let id = ObjectId::from_base36("01").unwrap();
+        let stop = Stop::parse(id, "48", &mut Vec::new()).unwrap();
+        assert_eq!(stop.seconds(60.0), 1.0);
+        assert_eq!(stop.seconds(120.0), 0.5);
+        assert_eq!(
+            Stop::parse(id, "192", &mut Vec::new())
+                .unwrap()
+                .seconds(60.0),
+            4.0
+        );
+    }
+
+    #[test]
+    fn every_lnobj_is_kept() {
+        let (header, _) = parse("#LNOBJ ZZ\n#LNOBJ YY\n");
+        let zz = ObjectId::from_base36("ZZ").unwrap();
+        let yy = ObjectId::from_base36("YY").unwrap();
+        assert!(header.is_lnobj(zz));
+        assert!(header.is_lnobj(yy));
+        assert!(!header.is_lnobj(ObjectId::from_base36("01").unwrap()));
+        assert_eq!(header.lnobj_ids().len(), 2);
+    }
+
+    #[test]
+    fn wav_redefinition() {
+        let (header, _) = parse("#WAV01 a.wav\n#WAV02 b.wav\n#WAV01 c.wav\n");
+        let id = ObjectId::from_base36("01").unwrap();
+        assert_eq!(header.wav(id).unwrap().filename(), "c.wav");
+        let history: Vec<_> = header
+            .wav_history(id)
+            .into_iter()
+            .map(Wav::filename)
+            .collect();
+        assert_eq!(history, ["a.wav", "c.wav"]);
+        assert!(header.wav_history(ObjectId::ZERO).is_empty());
+    }
+
+    #[test]
+    fn wav_backslashes() {
+        let (header, _) = parse("#WAV01 drums\\kick.wav\n#WAV02 snare.wav\n");
+        let [kick, snare] = header.wavs() else {
+            panic!("{:?}", header.wavs());
+        };
+        assert_eq!(kick.filename(), "drums\\kick.wav");
+        assert_eq!(kick.path(), "drums/kick.wav");
+        assert_eq!(
+            header.wav_file(kick.id()).as_deref(),
+            Some("drums/kick.wav")
+        );
+        assert_eq!(snare.path(), "snare.wav");
+    }
+
+    #[test]
+    fn defexrank_takes_precedence() {
+        let (header, warnings) = parse("#DEFEXRANK 120\n#RANK 3\n");
+        assert_eq!(header.rank(), &Rank::Easy);
+        assert_eq!(header.defexrank(), Some(120.0));
+        assert_eq!(header.effective_judge(), JudgeRankType::Defexrank(120.0));
+        assert_eq!(
+            warnings,
+            [ParseWarning::RankAndDefexrank {
+                rank: Rank::Easy,
+                defexrank: 120.0
+            }]
+        );
+
+        let (header, warnings) = parse("#RANK 1\n");
+        assert_eq!(header.effective_judge(), JudgeRankType::Rank(Rank::Hard));
+        assert!(warnings.is_empty());
+
+        let (header, warnings) = parse("#DEFEXRANK 80\n");
+        assert_eq!(header.effective_judge(), JudgeRankType::Defexrank(80.0));
+        assert!(warnings.is_empty());
+    }
+}
@@ -0,0 +1,143 @@
+use alloc::string::String;
+use core::fmt;
+
+use crate::header::Base;
+
+/// A two digit object identifier, `00` to `ZZ`, or `00` to `zz` under
+/// `#BASE 62`.
+///
+/// This is the `xx` in `#WAVxx`, `#BMPxx`, `#BPMxx`, `#STOPxx` and friends, and
+/// what channel data refers to them by.
+///
+/// Base-36 is case insensitive, so `aa` and `AA` are the same id, and we
+/// always print the uppercase form. In base 62 lowercase letters are digits of
+/// their own, after the uppercase ones, so `aa` and `AA` are different ids.
+///
+/// The value is the id read in base 62, with a base-36 id taken in uppercase.
+/// The two bases agree on every id they share, so `0A` is the same object
+/// whichever way it was read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObjectId(u32);
+
+impl ObjectId {
+    /// `00`, which channel data uses for an empty slot.
+    pub const ZERO: ObjectId = ObjectId(0);
+
+    /// The largest id, `zz` in base 62.
+    pub const MAX: ObjectId = ObjectId(62 * 62 - 1);
+
+    /// Decodes a two character id in the given base.
+    ///
+    /// Returns `None` if `id` isn't exactly two digits of that base.
+    pub fn parse(id: &str, base: &Base) -> Option<Self> {
+        match base {
+            Base::Base36 => Self::from_base36(id),
+            Base::Base62 => Self::from_base62(id),
+        }
+    }
+
+    /// Decodes a two character base-36 id.
+    ///
+    /// Returns `None` if `id` isn't exactly two base-36 digits.
+    pub fn from_base36(id: &str) -> Option<Self> {
+        let &[high, low] = id.as_bytes() else {
+            return None;
+        };
+        Self::from_digits(high.to_ascii_uppercase(), low.to_ascii_uppercase())
+    }
+
+    /// Decodes a two character base-62 id.
+    ///
+    /// Returns `None` if `id` isn't exactly two base-62 digits.
+    pub fn from_base62(id: &str) -> Option<Self> {
+        let &[high, low] = id.as_bytes() else {
+            return None;
+        };
+        Self::from_digits(high, low)
+    }
+
+    fn from_digits(high: u8, low: u8) -> Option<Self> {
+        Some(Self(base62_digit(high)? * 62 + base62_digit(low)?))
+    }
+
+    /// The numeric value of the id read in base 62, `0` to `3843`.
+    pub fn value(&self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut encoded = String::with_capacity(2);
+        encoded.push(base62_char(self.0 / 62));
+        encoded.push(base62_char(self.0 % 62));
+        f.write_str(&encoded)
+    }
+}
+
+fn base62_digit(digit: u8) -> Option<u32> {
+    match digit {
+        b'0'..=b'9' => Some((digit - b'0') as u32),
+        b'A'..=b'Z' => Some((digit - b'A') as u32 + 10),
+        b'a'..=b'z' => Some((digit - b'a') as u32 + 36),
+        _ => None,
+    }
+}
+
+fn base62_char(digit: u32) -> char {
+    match digit {
+        0..=9 => (b'0' + digit as u8) as char,
+        10..=35 => (b'A' + (digit - 10) as u8) as char,
+        36..=61 => (b'a' + (digit - 36) as u8) as char,
+        _ => '?',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn round_trips_every_id() {
+        for value in 0..=ObjectId::MAX.value() {
+            let id = ObjectId(value);
+            assert_eq!(ObjectId::from_base62(&id.to_string()), Some(id));
+        }
+        for id in ["00", "0Z", "Z0", "ZZ"] {
+            assert_eq!(ObjectId::from_base36(id).unwrap().to_string(), id);
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(ObjectId::ZERO.to_string(), "00");
+        assert_eq!(ObjectId::MAX.to_string(), "zz");
+        assert_eq!(ObjectId::from_base36("1z").unwrap().to_string(), "1Z");
+        assert_eq!(ObjectId::from_base62("1z").unwrap().to_string(), "1z");
+    }
+
+    #[test]
+    fn case_insensitive() {
+        assert_eq!(ObjectId::from_base36("aa"), ObjectId::from_base36("AA"));
+        assert_eq!(ObjectId::from_base36("0A").unwrap().value(), 10);
+    }
+
+    #[test]
+    fn base_62_is_case_sensitive() {
+        let upper = ObjectId::parse("AA", &Base::Base62).unwrap();
+        let lower = ObjectId::parse("aa", &Base::Base62).unwrap();
+        assert_ne!(upper, lower);
+        assert_eq!(lower.value(), 36 * 62 + 36);
+        assert_eq!(ObjectId::parse("AA", &Base::Base36), Some(upper));
+        assert_eq!(ObjectId::parse("aa", &Base::Base36), Some(upper));
+    }
+
+    #[test]
+    fn rejects_malformed_ids() {
+        for id in ["", "1", "123", "!!", "0-", "１２"] {
+            assert_eq!(ObjectId::from_base36(id), None, "{id}");
+            assert_eq!(ObjectId::from_base62(id), None, "{id}");
+        }
+    }
+}
@@ -1 +1,14 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod channel;
+pub mod error;
+pub mod fraction;
 pub mod header;
+pub mod id;
+pub mod line;
+pub mod parse;
+pub mod stats;
+pub mod validate;
+pub mod visit;
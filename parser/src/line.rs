@@ -0,0 +1,135 @@
+/// What a single line of a chart is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind<'a> {
+    /// Nothing but whitespace.
+    Blank,
+    /// Anything not starting with `#`, which BMS ignores.
+    ///
+    /// A lone `#` with no command name is treated as one too.
+    Comment,
+    /// `#NAME value`. The value is trimmed, and empty if there isn't one.
+    Command { name: &'a str, value: &'a str },
+    /// `#xxxCC:data`, channel data for measure `xxx` on channel `CC`.
+    Channel {
+        measure: &'a str,
+        channel: &'a str,
+        data: &'a str,
+    },
+}
+
+/// Classifies a line without allocating.
+///
+/// This is the hottest path in parsing, as every line of the file goes
+/// through it, so it only looks at as many bytes as it needs to.
+pub fn classify_line(line: &str) -> LineKind<'_> {
+    let line = line.trim_ascii();
+    let Some(rest) = line.strip_prefix('#') else {
+        return if line.is_empty() {
+            LineKind::Blank
+        } else {
+            LineKind::Comment
+        };
+    };
+
+    let bytes = rest.as_bytes();
+    if bytes.len() >= 6
+        && bytes[5] == b':'
+        && bytes[..3].iter().all(u8::is_ascii_digit)
+        && bytes[3..5].iter().all(u8::is_ascii_alphanumeric)
+    {
+        // All ASCII up to here, so these are char boundaries.
+        return LineKind::Channel {
+            measure: &rest[..3],
+            channel: &rest[3..5],
+            data: rest[6..].trim_ascii(),
+        };
+    }
+
+    let (name, value) = match bytes.iter().position(u8::is_ascii_whitespace) {
+        Some(end) => (&rest[..end], rest[end..].trim_ascii()),
+        None => (rest, ""),
+    };
+    if name.is_empty() {
+        return LineKind::Comment;
+    }
+    LineKind::Command { name, value }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blank() {
+        assert_eq!(classify_line(""), LineKind::Blank);
+        assert_eq!(classify_line(" \t\r"), LineKind::Blank);
+    }
+
+    #[test]
+    fn comment() {
+        assert_eq!(classify_line("comment"), LineKind::Comment);
+        assert_eq!(classify_line("// #TITLE x"), LineKind::Comment);
+        assert_eq!(classify_line("#"), LineKind::Comment);
+        assert_eq!(classify_line("# TITLE"), LineKind::Comment);
+    }
+
+    #[test]
+    fn command() {
+        assert_eq!(
+            classify_line("  #TITLE  Song  Name \r"),
+            LineKind::Command {
+                name: "TITLE",
+                value: "Song  Name"
+            }
+        );
+        assert_eq!(
+            classify_line("#RANDOM"),
+            LineKind::Command {
+                name: "RANDOM",
+                value: ""
+            }
+        );
+        assert_eq!(
+            classify_line("#123"),
+            LineKind::Command {
+                name: "123",
+                value: ""
+            }
+        );
+        assert_eq!(
+            classify_line("#タイトル 曲"),
+            LineKind::Command {
+                name: "タイトル",
+                value: "曲"
+            }
+        );
+    }
+
+    #[test]
+    fn channel() {
+        assert_eq!(
+            classify_line("#00111:00AA00BB "),
+            LineKind::Channel {
+                measure: "001",
+                channel: "11",
+                data: "00AA00BB"
+            }
+        );
+        assert_eq!(
+            classify_line("#999a0:"),
+            LineKind::Channel {
+                measure: "999",
+                channel: "a0",
+                data: ""
+            }
+        );
+        // Not enough digits for a measure, so just an odd command.
+        assert_eq!(
+            classify_line("#0111:01"),
+            LineKind::Command {
+                name: "0111:01",
+                value: ""
+            }
+        );
+    }
+}
@@ -0,0 +1,794 @@
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::ControlFlow;
+
+use crate::error::{ParseError, ParseWarning};
+use crate::header::{
+    Argb, Artist, BackBmp, Banner, Base, Bmp, ConstantBPM, DefExRank, Difficulty, DivideProp,
+    Email, ExBPM, ExBmp, Genre, Header, ImageIndex, LNObj, LNType, LnMode, Maker, PathWav,
+    PlayLevel, PlayOption, Player, Rank, Stagefile, Stop, Subartist, Subtitle, Title, Total, Url,
+    Volwav, Wav, fold_width,
+};
+use crate::id::ObjectId;
+use crate::visit::{HeaderVisitor, parse_visit};
+
+/// How much of a chart [`parse_header`] reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeaderScan {
+    /// Stop at the first channel-data line.
+    ///
+    /// Header commands conventionally precede the measure data, so this is
+    /// all a song list needs, and skips the bulk of the file.
+    #[default]
+    StopAtBody,
+    /// Read the whole file, for charts that put header commands after the
+    /// measure data.
+    WholeFile,
+}
+
+/// Callbacks for commands the parser doesn't know, by command name.
+///
+/// For tools with their own `#` commands. Whenever [`parse_header_with`] comes
+/// across an unknown command that has a handler, the handler is called with
+/// the command's trimmed argument. Names are case insensitive, like every
+/// other command.
+///
+/// A handler can borrow from its surroundings, say to collect values into a
+/// `Vec`, for as long as the registry lives.
+#[derive(Default)]
+pub struct CommandHandlers<'h> {
+    handlers: BTreeMap<String, Handler<'h>>,
+}
+
+type Handler<'h> = Box<dyn FnMut(&str) + 'h>;
+
+impl<'h> CommandHandlers<'h> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls `handler` for every `#<command>`, replacing any handler already
+    /// registered for it.
+    ///
+    /// Commands the parser understands itself never reach a handler.
+    pub fn register(&mut self, command: &str, handler: impl FnMut(&str) + 'h) {
+        self.handlers
+            .insert(command.to_ascii_uppercase(), Box::new(handler));
+    }
+
+    /// Calls the handler for `command`, if there is one.
+    fn call(&mut self, command: &str, value: &str) {
+        if let Some(handler) = self.handlers.get_mut(&command.to_ascii_uppercase()) {
+            handler(value);
+        }
+    }
+}
+
+/// Parses only the header of a chart, stopping at the first channel-data line.
+///
+/// See [`parse_header`] for charts that interleave header commands with the
+/// measure data, or to get at any warnings.
+pub fn parse_header_only(input: &str) -> Result<Header, ParseError> {
+    parse_header(input, HeaderScan::StopAtBody, &mut Vec::new())
+}
+
+/// Parses the header commands of a chart, starting from [`Header::default`].
+///
+/// Later commands override earlier ones. Anything we recover from is pushed
+/// onto `warnings`, including a command whose value doesn't parse, which is
+/// ignored with a [`ParseWarning::InvalidValue`]. Only a malformed object id,
+/// which channel data could never refer to, is an error.
+///
+/// A download that was cut off ends part way through a line. If that last,
+/// unterminated line fails to parse, it also gets a
+/// [`ParseWarning::TruncatedInput`], and a malformed id there is a warning
+/// rather than an error. This only catches cuts that leave something
+/// unparseable. `#WAV01 kic` is a perfectly good `#WAV` of a file named `kic`,
+/// and a cut down to `#WAV0` or into channel data isn't a header command at
+/// all, so neither is noticed. We can't tell a cut off file from one that's
+/// just missing its final newline either, so the same goes for those.
+pub fn parse_header(
+    input: &str,
+    scan: HeaderScan,
+    warnings: &mut Vec<ParseWarning>,
+) -> Result<Header, ParseError> {
+    parse_header_with(input, scan, warnings, &mut CommandHandlers::new())
+}
+
+/// [`parse_header`], calling `handlers` for any commands it doesn't know.
+pub fn parse_header_with(
+    input: &str,
+    scan: HeaderScan,
+    warnings: &mut Vec<ParseWarning>,
+    handlers: &mut CommandHandlers<'_>,
+) -> Result<Header, ParseError> {
+    let mut builder = HeaderBuilder {
+        header: Header::default(),
+        scan,
+        warnings,
+        error: None,
+        filenames: BTreeSet::new(),
+        has_rank: false,
+        has_ids: false,
+        handlers,
+    };
+    let (complete, unterminated) = match input.rfind('\n') {
+        Some(end) => input.split_at(end + 1),
+        None => ("", input),
+    };
+    if parse_visit(complete, &mut builder).is_continue() {
+        let seen = builder.warnings.len();
+        let _ = parse_visit(unterminated, &mut builder);
+        let invalid = builder.warnings[seen..]
+            .iter()
+            .any(|warning| matches!(warning, ParseWarning::InvalidValue { .. }));
+        if builder.error.take().is_some() || invalid {
+            builder.warnings.push(ParseWarning::TruncatedInput {
+                line: unterminated.to_string(),
+            });
+        }
+    }
+    if builder.has_rank
+        && let Some(defexrank) = builder.header.defexrank()
+    {
+        builder.warnings.push(ParseWarning::RankAndDefexrank {
+            rank: builder.header.rank.clone(),
+            defexrank,
+        });
+    }
+    match builder.error {
+        Some(error) => Err(error),
+        None => Ok(builder.header),
+    }
+}
+
+struct HeaderBuilder<'w, 'h> {
+    header: Header,
+    scan: HeaderScan,
+    warnings: &'w mut Vec<ParseWarning>,
+    error: Option<ParseError>,
+    /// Every resource filename seen so far, so repeats can share storage.
+    filenames: BTreeSet<Arc<str>>,
+    /// Whether the chart gave a `#RANK`, rather than leaving the default.
+    has_rank: bool,
+    /// Whether any object has been defined by id, so that a late `#BASE` can
+    /// be pointed out.
+    has_ids: bool,
+    handlers: &'w mut CommandHandlers<'h>,
+}
+
+impl HeaderBuilder<'_, '_> {
+    /// Stops the scan on the first error, so that it can be returned.
+    ///
+    /// A command whose value doesn't parse is only a warning. The command is
+    /// ignored, leaving the default or whatever came before, so one odd
+    /// `#PLAYLEVEL` doesn't lose the whole chart.
+    fn check(&mut self, result: Result<(), ParseError>) -> ControlFlow<()> {
+        match result {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(ParseError::InvalidValue { command, value }) => {
+                self.warnings
+                    .push(ParseWarning::InvalidValue { command, value });
+                ControlFlow::Continue(())
+            }
+            Err(error) => {
+                self.error = Some(error);
+                ControlFlow::Break(())
+            }
+        }
+    }
+
+    fn intern(&mut self, filename: Arc<str>) -> Arc<str> {
+        match self.filenames.get(&filename) {
+            Some(interned) => interned.clone(),
+            None => {
+                self.filenames.insert(filename.clone());
+                filename
+            }
+        }
+    }
+
+    /// Folds full-width digits in a numeric argument to ASCII, with a warning.
+    ///
+    /// Japanese IMEs make it easy to type `１３０` for `130`, and plenty of
+    /// charts have.
+    fn ascii_digits<'v>(&mut self, command: &str, value: &'v str) -> Cow<'v, str> {
+        if !value.contains(|c| matches!(c, '\u{FF10}'..='\u{FF19}')) {
+            return Cow::Borrowed(value);
+        }
+        self.warnings.push(ParseWarning::FullWidthDigits {
+            command: command.to_string(),
+            value: value.to_string(),
+        });
+        Cow::Owned(value.chars().map(fold_width).collect())
+    }
+
+    /// Tidies up a decimal argument, on top of [`Self::ascii_digits`].
+    ///
+    /// A decimal comma, as in `1,5`, is read as a point, with a warning. A
+    /// comma followed by exactly three digits, as in `#TOTAL 1,000`, could as
+    /// well be a thousands separator, so that command is ignored with the
+    /// same warning. Scientific notation like `1.3e2` is almost always a
+    /// mistake rather than a BPM of 130, so the command is ignored with a
+    /// warning too. An ignored command gives `None`.
+    fn decimal<'v>(&mut self, command: &str, value: &'v str) -> Option<Cow<'v, str>> {
+        let value = self.ascii_digits(command, value);
+        if value.contains(['e', 'E']) {
+            self.warnings.push(ParseWarning::ScientificNotation {
+                command: command.to_string(),
+                value: value.into_owned(),
+            });
+            return None;
+        }
+        if value.contains(',') && !value.contains('.') {
+            self.warnings.push(ParseWarning::DecimalComma {
+                command: command.to_string(),
+                value: value.to_string(),
+            });
+            let thousands = value.trim().split_once(',').is_some_and(|(_, fraction)| {
+                fraction.len() == 3 && fraction.bytes().all(|digit| digit.is_ascii_digit())
+            });
+            if thousands {
+                return None;
+            }
+            return Some(Cow::Owned(value.replacen(',', ".", 1)));
+        }
+        Some(value)
+    }
+
+    /// Decodes an object id in the chart's base.
+    ///
+    /// A one character id, from old charts that write `#WAV1` for `#WAV01`,
+    /// is padded with a leading `0`, with a warning.
+    fn id(&mut self, command: &str, id: &str) -> Result<ObjectId, ParseError> {
+        self.has_ids = true;
+        let padded;
+        let id = if id.chars().count() == 1 {
+            self.warnings.push(ParseWarning::ShortIdentifier {
+                command: command.to_string(),
+                id: id.to_string(),
+            });
+            padded = format!("0{id}");
+            &padded
+        } else {
+            id
+        };
+        ObjectId::parse(id, &self.header.base).ok_or_else(|| ParseError::InvalidIdentifier {
+            command: command.to_string(),
+            id: id.to_string(),
+        })
+    }
+}
+
+impl HeaderVisitor for HeaderBuilder<'_, '_> {
+    fn on_player(&mut self, value: &str) -> ControlFlow<()> {
+        let value = self.ascii_digits("PLAYER", value);
+        let result = Player::parse(&value).map(|player| self.header.player = player);
+        self.check(result)
+    }
+
+    fn on_base(&mut self, value: &str) -> ControlFlow<()> {
+        let value = self.ascii_digits("BASE", value);
+        let result = Base::parse(&value).map(|base| {
+            if self.has_ids || !self.header.lnobj_ids.is_empty() {
+                self.warnings.push(ParseWarning::BaseAfterDefinitions);
+            }
+            self.header.base = base;
+        });
+        self.check(result)
+    }
+
+    fn on_rank(&mut self, value: &str) -> ControlFlow<()> {
+        let value = self.ascii_digits("RANK", value);
+        let result = Rank::parse(&value).map(|rank| {
+            self.header.rank = rank;
+            self.has_rank = true;
+        });
+        self.check(result)
+    }
+
+    fn on_defexrank(&mut self, value: &str) -> ControlFlow<()> {
+        let value = self.ascii_digits("DEFEXRANK", value);
+        let result = DefExRank::parse(&value).map(|rank| self.header.defexrank = Some(rank));
+        self.check(result)
+    }
+
+    fn on_total(&mut self, value: &str) -> ControlFlow<()> {
+        let Some(value) = self.decimal("TOTAL", value) else {
+            return ControlFlow::Continue(());
+        };
+        let result = Total::parse(&value).map(|total| self.header.total = total);
+        self.check(result)
+    }
+
+    fn on_volwav(&mut self, value: &str) -> ControlFlow<()> {
+        let value = self.ascii_digits("VOLWAV", value);
+        let result = Volwav::parse(&value).map(|volwav| self.header.volwav = volwav);
+        self.check(result)
+    }
+
+    fn on_stagefile(&mut self, value: &str) -> ControlFlow<()> {
+        self.header.stagefile = Some(Stagefile(value.to_string()));
+        ControlFlow::Continue(())
+    }
+
+    fn on_banner(&mut self, value: &str) -> ControlFlow<()> {
+        self.header.banner = Some(Banner(value.to_string()));
+        ControlFlow::Continue(())
+    }
+
+    fn on_backbmp(&mut self, value: &str) -> ControlFlow<()> {
+        self.header.backbmp = Some(BackBmp(value.to_string()));
+        ControlFlow::Continue(())
+    }
+
+    fn on_playlevel(&mut self, value: &str) -> ControlFlow<()> {
+        let value = self.ascii_digits("PLAYLEVEL", value);
+        let result = PlayLevel::parse(&value).map(|level| self.header.playlevel = level);
+        self.check(result)
+    }
+
+    fn on_difficulty(&mut self, value: &str) -> ControlFlow<()> {
+        let value = self.ascii_digits("DIFFICULTY", value);
+        let result = Difficulty::parse(&value).map(|diff| self.header.difficulty = Some(diff));
+        self.check(result)
+    }
+
+    fn on_title(&mut self, value: &str) -> ControlFlow<()> {
+        self.header.title = Some(Title(value.to_string()));
+        ControlFlow::Continue(())
+    }
+
+    fn on_subtitle(&mut self, value: &str) -> ControlFlow<()> {
+        let replaced = self.header.subtitle.replace(Subtitle(value.to_string()));
+        if let Some(Subtitle(replaced)) = replaced {
+            self.warnings
+                .push(ParseWarning::DuplicateSubtitle { replaced });
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn on_artist(&mut self, value: &str) -> ControlFlow<()> {
+        self.header.artist = Some(Artist(value.to_string()));
+        ControlFlow::Continue(())
+    }
+
+    fn on_subartist(&mut self, value: &str) -> ControlFlow<()> {
+        self.header.subartist = Some(Subartist(value.to_string()));
+        ControlFlow::Continue(())
+    }
+
+    fn on_maker(&mut self, value: &str) -> ControlFlow<()> {
+        self.header.maker = Some(Maker(value.to_string()));
+        ControlFlow::Continue(())
+    }
+
+    fn on_genre(&mut self, value: &str) -> ControlFlow<()> {
+        self.header.genre = Genre(value.to_string());
+        ControlFlow::Continue(())
+    }
+
+    fn on_url(&mut self, value: &str) -> ControlFlow<()> {
+        self.header.url = Some(Url::parse(value, self.warnings));
+        ControlFlow::Continue(())
+    }
+
+    fn on_email(&mut self, value: &str) -> ControlFlow<()> {
+        self.header.email = Some(Email::parse(value, self.warnings));
+        ControlFlow::Continue(())
+    }
+
+    fn on_bpm(&mut self, value: &str) -> ControlFlow<()> {
+        let Some(value) = self.decimal("BPM", value) else {
+            return ControlFlow::Continue(());
+        };
+        let result = ConstantBPM::parse(&value).map(|bpm| self.header.bpm = bpm);
+        self.check(result)
+    }
+
+    fn on_lntype(&mut self, value: &str) -> ControlFlow<()> {
+        let value = self.ascii_digits("LNTYPE", value);
+        let result = LNType::parse(&value).map(|lntype| self.header.lntype = Some(lntype));
+        self.check(result)
+    }
+
+    fn on_lnobj(&mut self, value: &str) -> ControlFlow<()> {
+        let result = LNObj::parse(value, &self.header.base).map(|lnobj| {
+            self.header.lnobj_ids.insert(lnobj.id());
+        });
+        self.check(result)
+    }
+
+    fn on_lnmode(&mut self, value: &str) -> ControlFlow<()> {
+        let value = self.ascii_digits("LNMODE", value);
+        let result = LnMode::parse(&value).map(|mode| self.header.ln_mode = Some(mode));
+        self.check(result)
+    }
+
+    fn on_divideprop(&mut self, value: &str) -> ControlFlow<()> {
+        let value = self.ascii_digits("DIVIDEPROP", value);
+        self.warnings.push(ParseWarning::ObsoleteCommand {
+            command: "DIVIDEPROP".to_string(),
+        });
+        let result = DivideProp::parse(&value).map(|divide| self.header.divideprop = Some(divide));
+        self.check(result)
+    }
+
+    fn on_extchr(&mut self, value: &str) -> ControlFlow<()> {
+        self.warnings.push(ParseWarning::ObsoleteCommand {
+            command: "ExtChr".to_string(),
+        });
+        self.header.extchr.push(value.to_string());
+        ControlFlow::Continue(())
+    }
+
+    fn on_option(&mut self, value: &str) -> ControlFlow<()> {
+        let options = PlayOption::parse(value, self.warnings);
+        self.header.options.extend(options);
+        ControlFlow::Continue(())
+    }
+
+    fn on_path_wav(&mut self, value: &str) -> ControlFlow<()> {
+        let result = PathWav::parse(value).map(|path| self.header.wav_path = Some(path));
+        self.check(result)
+    }
+
+    fn on_wav(&mut self, id: &str, value: &str) -> ControlFlow<()> {
+        let result = self
+            .id("WAV", id)
+            .and_then(|id| Wav::parse(id, value))
+            .map(|mut wav| {
+                wav.1 = self.intern(wav.1);
+                wav.2 = self.intern(wav.2);
+                self.header.wavs.push(wav)
+            });
+        self.check(result)
+    }
+
+    fn on_bmp(&mut self, id: &str, value: &str) -> ControlFlow<()> {
+        let result = self
+            .id("BMP", id)
+            .and_then(|id| Bmp::parse(id, value))
+            .map(|mut bmp| {
+                bmp.1 = self.intern(bmp.1);
+                let index = ImageIndex::Bmp(self.header.bmps.len());
+                self.header.images.push(index);
+                self.header.bmps.push(bmp)
+            });
+        self.check(result)
+    }
+
+    fn on_exbmp(&mut self, id: &str, value: &str) -> ControlFlow<()> {
+        let result = self
+            .id("EXBMP", id)
+            .and_then(|id| ExBmp::parse(id, value))
+            .map(|exbmp| {
+                let index = ImageIndex::ExBmp(self.header.exbmps.len());
+                self.header.images.push(index);
+                self.header.exbmps.push(exbmp)
+            });
+        self.check(result)
+    }
+
+    fn on_argb(&mut self, layer: &str, value: &str) -> ControlFlow<()> {
+        let result =
+            Argb::parse(layer, value, self.warnings).map(|argb| self.header.argbs.extend(argb));
+        self.check(result)
+    }
+
+    fn on_exbpm(&mut self, id: &str, value: &str) -> ControlFlow<()> {
+        let Some(value) = self.decimal(&format!("BPM{id}"), value) else {
+            return ControlFlow::Continue(());
+        };
+        let result = self
+            .id("BPM", id)
+            .and_then(|id| ExBPM::parse(id, &value))
+            .map(|exbpm| self.header.exbpms.push(exbpm));
+        self.check(result)
+    }
+
+    fn on_stop(&mut self, id: &str, value: &str) -> ControlFlow<()> {
+        let Some(value) = self.decimal(&format!("STOP{id}"), value) else {
+            return ControlFlow::Continue(());
+        };
+        let result = self
+            .id("STOP", id)
+            .and_then(|id| Stop::parse(id, &value, self.warnings))
+            .map(|stop| self.header.stops.push(stop));
+        self.check(result)
+    }
+
+    fn on_unknown(&mut self, command: &str, value: &str) -> ControlFlow<()> {
+        self.handlers.call(command, value);
+        ControlFlow::Continue(())
+    }
+
+    fn on_channel(&mut self, _measure: &str, _channel: &str, _data: &str) -> ControlFlow<()> {
+        match self.scan {
+            HeaderScan::StopAtBody => ControlFlow::Break(()),
+            HeaderScan::WholeFile => ControlFlow::Continue(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::Rank;
+
+    fn parse(input: &str) -> (Result<Header, ParseError>, Vec<ParseWarning>) {
+        let mut warnings = Vec::new();
+        let header = parse_header(input, HeaderScan::WholeFile, &mut warnings);
+        (header, warnings)
+    }
+
+    #[test]
+    fn invalid_values_keep_the_default() {
+        let (header, warnings) = parse("#PLAYLEVEL ★5\n#DIFFICULTY 0\n#RANK 4\n#TITLE x\n");
+        let header = header.unwrap();
+        assert_eq!(header.playlevel(), Header::default().playlevel());
+        assert_eq!(header.difficulty(), None);
+        assert_eq!(header.rank(), &Rank::Normal);
+        assert_eq!(header.title(), Some("x"));
+        let commands: Vec<_> = warnings
+            .iter()
+            .filter_map(|warning| match warning {
+                ParseWarning::InvalidValue { command, .. } => Some(command.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(commands, ["PLAYLEVEL", "DIFFICULTY", "RANK"]);
+    }
+
+    #[test]
+    fn invalid_value_keeps_the_earlier_one() {
+        let (header, _) = parse("#PLAYLEVEL 7\n#PLAYLEVEL ?\n");
+        assert_eq!(header.unwrap().playlevel(), 7);
+    }
+
+    #[test]
+    fn invalid_id_is_an_error() {
+        let (header, _) = parse("#WAV!! kick.wav\n#TITLE x\n");
+        assert!(header.is_err());
+    }
+
+    #[test]
+    fn truncated_last_line() {
+        let (header, warnings) = parse("#TITLE x\n#PLAYLEVEL ?");
+        assert_eq!(header.unwrap().title(), Some("x"));
+        assert!(warnings.contains(&ParseWarning::TruncatedInput {
+            line: "#PLAYLEVEL ?".to_string()
+        }));
+
+        // Cut off between a #WAV id and its filename.
+        let (header, warnings) = parse("#TITLE x\n#WAV01");
+        assert!(header.unwrap().wavs().is_empty());
+        assert!(
+            warnings
+                .iter()
+                .any(|warning| matches!(warning, ParseWarning::TruncatedInput { .. }))
+        );
+    }
+
+    #[test]
+    fn invalid_value_mid_file_is_not_truncation() {
+        let (header, warnings) = parse("#PLAYLEVEL ?\n#TITLE x\n");
+        assert!(header.is_ok());
+        assert!(
+            !warnings
+                .iter()
+                .any(|warning| matches!(warning, ParseWarning::TruncatedInput { .. }))
+        );
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let (header, warnings) = parse("\u{feff}#TITLE x");
+        assert_eq!(header.unwrap().title(), Some("x"));
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn unterminated_line_that_parses_is_not_noticed() {
+        let (header, warnings) = parse("#TITLE x\n#WAV01 kic");
+        assert_eq!(header.unwrap().wavs()[0].filename(), "kic");
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn base_62_ids_are_case_sensitive() {
+        let (header, warnings) = parse("#BASE 62\n#WAVaa a.wav\n#WAVAA b.wav\n");
+        let header = header.unwrap();
+        let lower = ObjectId::parse("aa", &Base::Base62).unwrap();
+        let upper = ObjectId::parse("AA", &Base::Base62).unwrap();
+        assert_ne!(lower, upper);
+        assert_eq!(header.wav(lower).unwrap().filename(), "a.wav");
+        assert_eq!(header.wav(upper).unwrap().filename(), "b.wav");
+        assert!(warnings.is_empty());
+
+        let (header, _) = parse("#WAVaa a.wav\n#WAVAA b.wav\n");
+        assert_eq!(header.unwrap().wav(upper).unwrap().filename(), "b.wav");
+    }
+
+    #[test]
+    fn short_identifiers_are_padded() {
+        let (header, warnings) = parse("#WAV1 foo.wav\n#BMPa bar.bmp\n");
+        let header = header.unwrap();
+        let id = ObjectId::from_base36("01").unwrap();
+        assert_eq!(header.wav(id).unwrap().filename(), "foo.wav");
+        assert!(header.bmp(ObjectId::from_base36("0A").unwrap()).is_some());
+        assert_eq!(
+            warnings,
+            [
+                ParseWarning::ShortIdentifier {
+                    command: "WAV".to_string(),
+                    id: "1".to_string(),
+                },
+                ParseWarning::ShortIdentifier {
+                    command: "BMP".to_string(),
+                    id: "a".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn base_after_definitions() {
+        let (_, warnings) = parse("#BASE 62\n#WAV01 a.wav\n");
+        assert!(warnings.is_empty());
+
+        let (_, warnings) = parse("#WAV01 a.wav\n#BASE 62\n");
+        assert_eq!(warnings, [ParseWarning::BaseAfterDefinitions]);
+    }
+
+    #[test]
+    fn negative_playlevel() {
+        let (header, warnings) = parse("#PLAYLEVEL -1\n");
+        assert_eq!(header.unwrap().playlevel(), -1);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn decimal_comma() {
+        let (header, warnings) = parse("#BPM 1,30\n#TOTAL 1,000\n");
+        let header = header.unwrap();
+        assert_eq!(header.bpm(), 1.3);
+        assert_eq!(header.total(), Header::default().total());
+        assert_eq!(
+            warnings,
+            [
+                ParseWarning::DecimalComma {
+                    command: "BPM".to_string(),
+                    value: "1,30".to_string(),
+                },
+                ParseWarning::DecimalComma {
+                    command: "TOTAL".to_string(),
+                    value: "1,000".to_string(),
+                },
+            ]
+        );
+
+        // The earlier value stands.
+        let (header, _) = parse("#TOTAL 250\n#TOTAL 1,000\n#BPM 1,5\n");
+        let header = header.unwrap();
+        assert_eq!(header.total(), 250.0);
+        assert_eq!(header.bpm(), 1.5);
+    }
+
+    #[test]
+    fn scientific_notation_is_ignored() {
+        let (header, warnings) = parse("#BPM 1.3e2\n");
+        assert_eq!(header.unwrap().bpm(), Header::default().bpm());
+        assert_eq!(
+            warnings,
+            [ParseWarning::ScientificNotation {
+                command: "BPM".to_string(),
+                value: "1.3e2".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn header_only_stops_at_the_body() {
+        let input = "#TITLE song\n#00111:01\n#ARTIST late\n";
+        let header = parse_header_only(input).unwrap();
+        assert_eq!(header.title(), Some("song"));
+        assert_eq!(header.artist(), None);
+
+        let (header, _) = parse(input);
+        assert_eq!(header.unwrap().artist(), Some("late"));
+    }
+
+    #[test]
+    fn repeated_filenames_share_storage() {
+        let (header, _) = parse("#WAV01 kick.wav\n#WAV02 kick.wav\n#BMP01 kick.wav\n");
+        let header = header.unwrap();
+        let [first, second] = header.wavs() else {
+            panic!("{:?}", header.wavs());
+        };
+        assert!(Arc::ptr_eq(&first.1, &second.1));
+        assert!(Arc::ptr_eq(&first.1, &header.bmps()[0].1));
+    }
+
+    #[test]
+    fn truncated_mid_channel_data() {
+        let (header, warnings) = parse("#TITLE x\n#WAV01 a.wav\n#00111:01\n#0021");
+        let header = header.unwrap();
+        assert_eq!(header.title(), Some("x"));
+        assert_eq!(header.wavs().len(), 1);
+        assert!(warnings.is_empty());
+
+        let (header, warnings) = parse("#TITLE x\n#STOP01 4");
+        assert_eq!(header.unwrap().stops()[0].duration(), 4);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn long_arguments_survive() {
+        let title = "あ".repeat(1000) + &"x".repeat(3000);
+        assert_eq!(title.chars().count(), 4000);
+        let (header, warnings) = parse(&format!("#TITLE {title}\n#WAV01 {title}.wav\n"));
+        let header = header.unwrap();
+        assert_eq!(header.title(), Some(title.as_str()));
+        assert_eq!(header.wavs()[0].filename(), format!("{title}.wav"));
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn full_width_digits() {
+        let (header, warnings) = parse("#BPM １３０\n#PLAYLEVEL １２\n");
+        let header = header.unwrap();
+        assert_eq!(header.bpm(), 130.0);
+        assert_eq!(header.playlevel(), 12);
+        assert_eq!(
+            warnings,
+            [
+                ParseWarning::FullWidthDigits {
+                    command: "BPM".to_string(),
+                    value: "１３０".to_string(),
+                },
+                ParseWarning::FullWidthDigits {
+                    command: "PLAYLEVEL".to_string(),
+                    value: "１２".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_command_handlers() {
+        let mut tags = Vec::new();
+        let mut handlers = CommandHandlers::new();
+        handlers.register("MYTAG", |value| tags.push(value.to_string()));
+        handlers.register("TITLE", |_| panic!("#TITLE is handled by the parser"));
+
+        let header = parse_header_with(
+            "#mytag first\n#TITLE song\n#OTHER x\n#MYTAG  second \n",
+            HeaderScan::WholeFile,
+            &mut Vec::new(),
+            &mut handlers,
+        )
+        .unwrap();
+        drop(handlers);
+
+        assert_eq!(header.title(), Some("song"));
+        assert_eq!(tags, ["first", "second"]);
+    }
+
+    #[test]
+    fn last_subtitle_wins() {
+        let (header, warnings) = parse("#SUBTITLE first\n#SUBTITLE second\n");
+        assert_eq!(header.unwrap().subtitle(), Some("second"));
+        assert_eq!(
+            warnings,
+            [ParseWarning::DuplicateSubtitle {
+                replaced: "first".to_string()
+            }]
+        );
+    }
+}
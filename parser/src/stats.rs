@@ -0,0 +1,482 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::ControlFlow;
+
+use crate::channel::{ChannelData, Side, note_side};
+use crate::header::{Base, Header, Player};
+use crate::id::ObjectId;
+use crate::validate::is_judged;
+use crate::visit::{HeaderVisitor, parse_visit};
+
+/// Rough numbers for a chart, read straight off its channel data.
+///
+/// Nothing is timed, so these are cheap enough to work out for a whole
+/// library, for sorting or filtering by.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuickStats {
+    /// Judged notes, with a long note counting once.
+    pub note_count: usize,
+    /// Measures up to and including the last one with any channel data.
+    pub measure_count: u16,
+    pub key_mode: KeyMode,
+}
+
+/// The layout of lanes a chart is played on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyMode {
+    /// 5 keys and a scratch.
+    Beat5,
+    /// 7 keys and a scratch.
+    Beat7,
+    /// 5 keys and a scratch a side.
+    Beat10,
+    /// 7 keys and a scratch a side.
+    Beat14,
+    /// pop'n music's 9 buttons, laid out as `11` to `15` and `22` to `25`.
+    PopN9,
+}
+
+/// Works out [`QuickStats`] for the chart in `input`.
+///
+/// The key mode comes from the lanes that have notes, since `#PLAYER` doesn't
+/// tell 5 keys from 7. A chart using lanes `22` to `25` and nothing else of
+/// the 2P side is taken for pop'n music, unless `#PLAYER` says double play.
+pub fn quick_stats(input: &str, header: &Header) -> QuickStats {
+    let channels = Channels::collect(input, header);
+    QuickStats {
+        note_count: channels.judged_notes(header).len(),
+        measure_count: channels.measure_count,
+        key_mode: channels.key_mode(header),
+    }
+}
+
+/// Judged notes on each side of a double play chart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DpBalance {
+    /// Notes on the 1P side, played with the left hand, scratch `16` included.
+    pub left: usize,
+    /// Notes on the 2P side, played with the right hand, scratch `26` included.
+    pub right: usize,
+}
+
+/// How the notes of a `#PLAYER 3` chart split between the hands.
+///
+/// Each scratch belongs to its own side. `None` for anything but double play,
+/// as the sides of couple and battle play are different players.
+pub fn dp_balance(input: &str, header: &Header) -> Option<DpBalance> {
+    if *header.player() != Player::Three {
+        return None;
+    }
+    let channels = Channels::collect(input, header);
+    let mut balance = DpBalance { left: 0, right: 0 };
+    for (_, channel) in channels.judged_notes(header) {
+        match note_side(channel) {
+            Some(Side::P1) => balance.left += 1,
+            Some(Side::P2) => balance.right += 1,
+            None => {}
+        }
+    }
+    Some(balance)
+}
+
+/// Judged notes in each measure, for drawing a density overview.
+///
+/// Every measure from 0 to the last one with channel data is listed, in
+/// order, with `0` for those without notes, so the result can be plotted
+/// directly.
+pub fn notes_per_measure(input: &str, header: &Header) -> Vec<(u16, usize)> {
+    let channels = Channels::collect(input, header);
+    let mut counts: Vec<_> = (0..channels.measure_count)
+        .map(|measure| (measure, 0))
+        .collect();
+    for (measure, _) in channels.judged_notes(header) {
+        counts[measure as usize].1 += 1;
+    }
+    counts
+}
+
+/// Which kinds of background animation a chart has, for song select filters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BgaPresence {
+    /// The base or a layer (`04`, `07` or `0A`) shows something.
+    pub has_bga: bool,
+    /// A BGA channel, poor included, shows a `#BMPxx` or `#EXBMPxx` that's a
+    /// video file.
+    pub has_video: bool,
+    /// The poor BGA (`06`), shown on a miss, shows something.
+    pub has_poor_bga: bool,
+}
+
+/// Works out [`BgaPresence`] for the chart in `input`.
+///
+/// Videos are told apart by the extension of the image's filename. An object
+/// on a BGA channel counts even if its image isn't defined.
+pub fn bga_presence(input: &str, header: &Header) -> BgaPresence {
+    let channels = Channels::collect(input, header);
+    let mut presence = BgaPresence {
+        has_bga: false,
+        has_video: false,
+        has_poor_bga: false,
+    };
+    for ((channel, _), data) in &channels.lines {
+        let flag = match channel.as_str() {
+            "04" | "07" | "0A" => &mut presence.has_bga,
+            "06" => &mut presence.has_poor_bga,
+            _ => continue,
+        };
+        let ChannelData::Base36(ids) = data else {
+            continue;
+        };
+        for &id in ids.iter().filter(|&&id| id != ObjectId::ZERO) {
+            *flag = true;
+            presence.has_video |= header
+                .bga_image(id)
+                .is_some_and(|image| is_video(image.filename()));
+        }
+    }
+    presence
+}
+
+/// Every distinct BPM the chart plays at, in ascending order.
+///
+/// That's `#BPM`, the integer changes on channel `03`, and the `#BPMxx` that
+/// channel `08` refers to. A `#BPMxx` nothing refers to isn't used, so isn't
+/// listed, nor is a reference to one that isn't defined. BPMs within 0.001 of
+/// each other count as one, as `#BPMxx` values are often written with more or
+/// fewer decimals than `#BPM`.
+pub fn unique_bpms(input: &str, header: &Header) -> Vec<f32> {
+    let channels = Channels::collect(input, header);
+    let mut bpms = vec![header.bpm()];
+    for ((channel, _), data) in &channels.lines {
+        match (channel.as_str(), data) {
+            ("03", ChannelData::Hex(values)) => bpms.extend(
+                values
+                    .iter()
+                    .filter(|&&value| value != 0)
+                    .map(|&value| value as f32),
+            ),
+            ("08", ChannelData::Base36(ids)) => bpms.extend(
+                ids.iter()
+                    .filter_map(|&id| header.exbpm(id))
+                    .map(|exbpm| exbpm.bpm()),
+            ),
+            _ => {}
+        }
+    }
+    bpms.sort_by(f32::total_cmp);
+    bpms.dedup_by(|later, earlier| *later - *earlier < BPM_TOLERANCE);
+    bpms
+}
+
+/// The BPM the chart starts at.
+///
+/// That's usually `#BPM`, but a change right at the start of measure `000`
+/// takes over before anything plays. When both channels have one there, the
+/// `#BPMxx` on `08` wins over the integer on `03`.
+pub fn initial_bpm(input: &str, header: &Header) -> f32 {
+    let channels = Channels::collect(input, header);
+    let start = |channel: &str| channels.lines.get(&(channel.to_string(), 0));
+    let extended = match start("08") {
+        Some(ChannelData::Base36(ids)) => ids
+            .first()
+            .filter(|&&id| id != ObjectId::ZERO)
+            .and_then(|&id| header.exbpm(id))
+            .map(|exbpm| exbpm.bpm()),
+        _ => None,
+    };
+    let integer = match start("03") {
+        Some(ChannelData::Hex(values)) => values
+            .first()
+            .filter(|&&value| value != 0)
+            .map(|&value| value as f32),
+        _ => None,
+    };
+    extended.or(integer).unwrap_or(header.bpm())
+}
+
+/// How far apart two BPMs can be and still count as the same.
+const BPM_TOLERANCE: f32 = 0.001;
+
+const VIDEO_EXTENSIONS: &[&str] = &["mpg", "mpeg", "mp4", "avi", "wmv", "webm"];
+
+fn is_video(filename: &str) -> bool {
+    filename.rsplit_once('.').is_some_and(|(_, extension)| {
+        VIDEO_EXTENSIONS
+            .iter()
+            .any(|video| extension.eq_ignore_ascii_case(video))
+    })
+}
+
+/// The channel data of a chart, gathered up by channel and measure.
+pub(crate) struct Channels {
+    /// Every line for a channel in a measure, merged into one. BGM `01` lines
+    /// are separate layers that can't be merged, and nothing here needs them.
+    lines: BTreeMap<(String, u16), ChannelData>,
+    measure_count: u16,
+}
+
+impl Channels {
+    pub(crate) fn collect(input: &str, header: &Header) -> Self {
+        let mut collector = Collector {
+            base: header.base(),
+            channels: Self {
+                lines: BTreeMap::new(),
+                measure_count: 0,
+            },
+        };
+        let _ = parse_visit(input, &mut collector);
+        collector.channels
+    }
+
+    /// The measure and channel of every judged note, in measure order for
+    /// each channel.
+    ///
+    /// A long note is one note. Objects on the `5x`/`6x` channels pair up
+    /// into a head and a tail, and on the visible channels an `#LNOBJ` id
+    /// ends the note before it rather than being one itself.
+    fn judged_notes(&self, header: &Header) -> Vec<(u16, &str)> {
+        let mut notes = Vec::new();
+        // Long note channels with a head waiting for its tail.
+        let mut holding = BTreeSet::new();
+        for ((channel, measure), data) in &self.lines {
+            let ChannelData::Base36(ids) = data else {
+                continue;
+            };
+            if !is_judged(channel) {
+                continue;
+            }
+            let long = channel.starts_with(['5', '6']);
+            for &id in ids.iter().filter(|&&id| id != ObjectId::ZERO) {
+                if long && !holding.insert(channel) {
+                    holding.remove(channel);
+                    continue;
+                }
+                if !long && header.is_lnobj(id) {
+                    continue;
+                }
+                notes.push((*measure, channel.as_str()));
+            }
+        }
+        notes
+    }
+
+    pub(crate) fn key_mode(&self, header: &Header) -> KeyMode {
+        // Lanes with any kind of note, as (whether it's 2P, lane).
+        let lanes: BTreeSet<(bool, u8)> = self
+            .lines
+            .iter()
+            .filter(|(_, data)| data.object_count() > 0)
+            .filter_map(|((channel, _), _)| {
+                let side = note_side(channel)?;
+                Some((side == Side::P2, channel.as_bytes()[1]))
+            })
+            .collect();
+        let seven = lanes.iter().any(|&(_, lane)| matches!(lane, b'8' | b'9'));
+        let p2: Vec<u8> = lanes
+            .iter()
+            .filter(|(p2, _)| *p2)
+            .map(|&(_, lane)| lane)
+            .collect();
+
+        if p2.is_empty() {
+            return if seven {
+                KeyMode::Beat7
+            } else {
+                KeyMode::Beat5
+            };
+        }
+        let popn = p2.iter().all(|lane| matches!(lane, b'2'..=b'5'))
+            && !lanes.contains(&(false, b'6'))
+            && !seven;
+        if popn && *header.player() != Player::Three {
+            KeyMode::PopN9
+        } else if seven {
+            KeyMode::Beat14
+        } else {
+            KeyMode::Beat10
+        }
+    }
+}
+
+/// Gathers up [`Channels`], reading ids in the chart's base.
+struct Collector<'h> {
+    base: &'h Base,
+    channels: Channels,
+}
+
+impl HeaderVisitor for Collector<'_> {
+    fn on_channel(&mut self, measure: &str, channel: &str, data: &str) -> ControlFlow<()> {
+        let Ok(measure) = measure.parse::<u16>() else {
+            return ControlFlow::Continue(());
+        };
+        let channels = &mut self.channels;
+        channels.measure_count = channels.measure_count.max(measure + 1);
+        if channel == "01" || channel == "02" {
+            return ControlFlow::Continue(());
+        }
+        let Ok(data) = ChannelData::parse(channel, data, self.base) else {
+            return ControlFlow::Continue(());
+        };
+
+        let key = (channel.to_ascii_uppercase(), measure);
+        let merged = match channels.lines.get(&key) {
+            Some(earlier) => earlier.merge(&data),
+            None => data,
+        };
+        channels.lines.insert(key, merged);
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{HeaderScan, parse_header};
+
+    fn header(input: &str) -> Header {
+        parse_header(input, HeaderScan::WholeFile, &mut Vec::new()).unwrap()
+    }
+
+    #[test]
+    fn quick_stats_counts_notes_and_measures() {
+        let input = "\
+#LNOBJ ZZ
+#00011:01000100
+#00011:00010001
+#00116:01
+#00151:0100
+#00251:0001
+#00212:01ZZ
+#00131:01
+#003D1:01
+#00401:01
+";
+        assert_eq!(
+            quick_stats(input, &header(input)),
+            QuickStats {
+                note_count: 7,
+                measure_count: 5,
+                key_mode: KeyMode::Beat5,
+            }
+        );
+    }
+
+    #[test]
+    fn key_modes() {
+        let key_mode = |input: &str| quick_stats(input, &header(input)).key_mode;
+        assert_eq!(key_mode("#00111:01\n#00116:01\n"), KeyMode::Beat5);
+        assert_eq!(key_mode("#00111:01\n#00119:01\n"), KeyMode::Beat7);
+        assert_eq!(key_mode("#00111:01\n#00126:01\n"), KeyMode::Beat10);
+        assert_eq!(key_mode("#00118:01\n#00121:01\n"), KeyMode::Beat14);
+        assert_eq!(key_mode("#00111:01\n#00125:01\n"), KeyMode::PopN9);
+        assert_eq!(
+            key_mode("#PLAYER 3\n#00111:01\n#00125:01\n"),
+            KeyMode::Beat10
+        );
+    }
+
+    #[test]
+    fn dp_balance_splits_by_side() {
+        let input = "\
+#PLAYER 3
+#00111:0101
+#00116:01
+#00121:01
+#00126:0101
+#00129:01
+#00162:0101
+";
+        assert_eq!(
+            dp_balance(input, &header(input)),
+            Some(DpBalance { left: 3, right: 5 })
+        );
+
+        let input = "#PLAYER 1\n#00111:01\n";
+        assert_eq!(dp_balance(input, &header(input)), None);
+    }
+
+    #[test]
+    fn notes_per_measure_is_contiguous() {
+        let input = "\
+#00011:01010101
+#00212:01
+#00213:0101
+#00401:01
+";
+        assert_eq!(
+            notes_per_measure(input, &header(input)),
+            [(0, 4), (1, 0), (2, 3), (3, 0), (4, 0)]
+        );
+        assert_eq!(notes_per_measure("", &header("")), []);
+    }
+
+    #[test]
+    fn bga_presence_with_video() {
+        let input = "\
+#BMP01 intro.mp4
+#BMP02 miss.png
+#BMP03 flash.MPG
+#00004:01
+#00006:02
+#00111:03
+";
+        assert_eq!(
+            bga_presence(input, &header(input)),
+            BgaPresence {
+                has_bga: true,
+                has_video: true,
+                has_poor_bga: true,
+            }
+        );
+
+        let input = "#EXBMP01 0,0,0,0 a.mp4\n#00004:01\n";
+        assert!(bga_presence(input, &header(input)).has_video);
+
+        let input = "#BMP01 still.png\n#BMP03 flash.mpg\n#00007:01\n#00111:03\n";
+        assert_eq!(
+            bga_presence(input, &header(input)),
+            BgaPresence {
+                has_bga: true,
+                has_video: false,
+                has_poor_bga: false,
+            }
+        );
+    }
+
+    #[test]
+    fn unique_bpms_only_lists_used_ones() {
+        let input = "\
+#BPM 150
+#BPM01 150.0001
+#BPM02 200
+#BPM03 90
+#00108:0100
+#00103:00C8
+#00208:02
+";
+        assert_eq!(unique_bpms(input, &header(input)), [150.0, 200.0]);
+        assert_eq!(unique_bpms("", &header("")), [Header::default().bpm()]);
+    }
+
+    #[test]
+    fn initial_bpm_at_the_very_start() {
+        let initial = |input: &str| initial_bpm(input, &header(input));
+        assert_eq!(initial("#BPM 150\n#00111:01\n"), 150.0);
+        assert_eq!(initial("#BPM 150\n#00003:B4\n"), 180.0);
+        assert_eq!(initial("#BPM 150\n#BPM01 175.5\n#00008:0100\n"), 175.5);
+        assert_eq!(
+            initial("#BPM 150\n#BPM01 175.5\n#00003:B4\n#00008:01\n"),
+            175.5
+        );
+        // Not at the start, so #BPM plays first.
+        assert_eq!(initial("#BPM 150\n#00003:00B4\n#00103:B4\n"), 150.0);
+    }
+
+    #[test]
+    fn every_lnobj_ends_a_long_note() {
+        let input = "#LNOBJ ZZ\n#LNOBJ YY\n#00111:01ZZ\n#00112:01YY\n";
+        assert_eq!(quick_stats(input, &header(input)).note_count, 2);
+    }
+}
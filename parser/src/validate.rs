@@ -0,0 +1,517 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::ops::ControlFlow;
+
+use crate::channel::{ChannelData, Side, note_side};
+use crate::error::{ParseWarning, ValidationError};
+use crate::header::{Base, Header, Player};
+use crate::id::ObjectId;
+use crate::line::{LineKind, classify_line};
+use crate::stats::{Channels, KeyMode};
+use crate::visit::{HeaderVisitor, parse_visit};
+
+/// Cross-checks the declared `#PLAYER` against the note channels `input` uses.
+///
+/// Single play charts only have notes on the 1P side, while couple and double
+/// play need the 2P side as well. Getting this wrong is a real authoring
+/// mistake, usually a DP chart that forgot its `#PLAYER 3`, so a mismatch is
+/// pushed onto `warnings` as a [`ParseWarning::PlayerSideMismatch`].
+///
+/// Battle play mirrors one side onto the other, so it is never a mismatch.
+/// Nor is a pop'n music chart, which puts four of its nine buttons on 2P lanes
+/// `22` to `25` under `#PLAYER 1`.
+pub fn check_player_side(input: &str, header: &Header, warnings: &mut Vec<ParseWarning>) {
+    let mut usage = SideUsage {
+        base: header.base(),
+        uses_p2: false,
+    };
+    let _ = parse_visit(input, &mut usage);
+
+    let player = header.player();
+    let expects_p2 = match player {
+        Player::One => false,
+        Player::Two | Player::Three => true,
+        Player::Four => return,
+    };
+    if usage.uses_p2 != expects_p2 {
+        if usage.uses_p2 && Channels::collect(input, header).key_mode(header) == KeyMode::PopN9 {
+            return;
+        }
+        warnings.push(ParseWarning::PlayerSideMismatch {
+            player: player.clone(),
+            uses_p2: usage.uses_p2,
+        });
+    }
+}
+
+struct SideUsage<'h> {
+    base: &'h Base,
+    uses_p2: bool,
+}
+
+impl HeaderVisitor for SideUsage<'_> {
+    fn on_channel(&mut self, _measure: &str, channel: &str, data: &str) -> ControlFlow<()> {
+        if note_side(channel) != Some(Side::P2) {
+            return ControlFlow::Continue(());
+        }
+        let populated =
+            ChannelData::parse(channel, data, self.base).is_ok_and(|data| data.object_count() > 0);
+        if populated {
+            self.uses_p2 = true;
+            return ControlFlow::Break(());
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Warns about a `#PLAYLEVEL` above `threshold`.
+///
+/// Any whole number parses as a level, as insane tables go well past the usual
+/// IIDX style 1 to 12. A level beyond what the caller expects might still be a
+/// typo though, so it's pushed onto `warnings` as a
+/// [`ParseWarning::HighPlayLevel`]. `12` is a sensible threshold for a normal
+/// song list.
+pub fn check_playlevel(header: &Header, threshold: i32, warnings: &mut Vec<ParseWarning>) {
+    let level = header.playlevel();
+    if level > threshold {
+        warnings.push(ParseWarning::HighPlayLevel { level });
+    }
+}
+
+/// Checks that every measure in `input` is divided up consistently.
+///
+/// Each channel line splits its measure into equal slots of two characters,
+/// so data of odd length can't be placed. A measure also only has one length,
+/// so a repeated `#xxx02` that disagrees with the first is ambiguous, as
+/// players differ on which one wins. Either is pushed onto `warnings` as a
+/// [`ParseWarning::InconsistentSubdivision`].
+pub fn check_subdivisions(input: &str, warnings: &mut Vec<ParseWarning>) {
+    let mut subdivisions = Subdivisions {
+        lengths: BTreeMap::new(),
+        warnings,
+    };
+    let _ = parse_visit(input, &mut subdivisions);
+}
+
+struct Subdivisions<'w> {
+    /// The first `#xxx02` length seen for each measure.
+    lengths: BTreeMap<String, String>,
+    warnings: &'w mut Vec<ParseWarning>,
+}
+
+impl Subdivisions<'_> {
+    fn warn(&mut self, measure: &str, channel: &str) {
+        self.warnings.push(ParseWarning::InconsistentSubdivision {
+            measure: measure.to_string(),
+            channel: channel.to_string(),
+        });
+    }
+}
+
+impl HeaderVisitor for Subdivisions<'_> {
+    fn on_channel(&mut self, measure: &str, channel: &str, data: &str) -> ControlFlow<()> {
+        if channel != "02" {
+            if !data.len().is_multiple_of(2) {
+                self.warn(measure, channel);
+            }
+            return ControlFlow::Continue(());
+        }
+
+        let conflicts = match self.lengths.get(measure) {
+            Some(first) => !same_length(first, data),
+            None => {
+                self.lengths.insert(measure.to_string(), data.to_string());
+                false
+            }
+        };
+        if conflicts {
+            self.warn(measure, channel);
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Whether two `#xxx02` values give the same length, as in `0.5` and `.50`.
+fn same_length(a: &str, b: &str) -> bool {
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a.eq_ignore_ascii_case(b),
+    }
+}
+
+/// Warns about channel data with whitespace after it, as in `#00111:0011 `.
+///
+/// The whitespace is trimmed before the data is split into pairs, so the line
+/// still reads as two objects. Some players don't, and see an odd length or
+/// a bogus extra object, so each such line is pushed onto `warnings` as a
+/// [`ParseWarning::TrailingWhitespace`].
+pub fn check_trailing_whitespace(input: &str, warnings: &mut Vec<ParseWarning>) {
+    for line in input.lines() {
+        let LineKind::Channel {
+            measure, channel, ..
+        } = classify_line(line)
+        else {
+            continue;
+        };
+        if line.ends_with(|c: char| c.is_ascii_whitespace()) {
+            warnings.push(ParseWarning::TrailingWhitespace {
+                measure: measure.to_string(),
+                channel: channel.to_string(),
+            });
+        }
+    }
+}
+
+/// Checks that `input` is a chart someone could actually play.
+///
+/// This gathers up the checks that make a chart unplayable, rather than just
+/// odd, so an importer can accept or reject a chart in one call:
+///
+/// - every `#WAVxx` that channel data plays is defined
+/// - `#BPM` is positive and no `#BPMxx` is zero, as negative `#BPMxx` is a
+///   deliberate gimmick
+/// - there is at least one note to hit
+///
+/// `#RANDOM` and friends aren't supported yet, so unterminated control flow
+/// isn't checked. See [`check_playable_in`] to check resources as well.
+pub fn check_playable(input: &str, header: &Header) -> Result<(), Vec<ValidationError>> {
+    let mut errors = Vec::new();
+
+    if header.bpm() <= 0.0 {
+        errors.push(ValidationError::InvalidBpm { bpm: header.bpm() });
+    }
+    for exbpm in header.exbpms() {
+        if exbpm.bpm() == 0.0 {
+            errors.push(ValidationError::InvalidBpm { bpm: exbpm.bpm() });
+        }
+    }
+
+    let mut playable = Playable {
+        header,
+        notes: 0,
+        undefined: BTreeSet::new(),
+        errors: &mut errors,
+    };
+    let _ = parse_visit(input, &mut playable);
+    if playable.notes == 0 {
+        errors.push(ValidationError::NoNotes);
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// [`check_playable`], and also checks that every `#WAVxx`, `#BMPxx` and
+/// `#EXBMPxx` file is in `dir`, the chart's directory.
+///
+/// A file counts as there if it exists with any extension players would
+/// search for in its place, such as `a.ogg` for `#WAV01 a.wav`. Only the
+/// definition in effect for each id is checked, as the others are never used.
+#[cfg(feature = "std")]
+pub fn check_playable_in(
+    input: &str,
+    header: &Header,
+    dir: &std::path::Path,
+) -> Result<(), Vec<ValidationError>> {
+    let mut errors = check_playable(input, header).err().unwrap_or_default();
+
+    let wav_ids: std::collections::BTreeSet<_> = header.wavs().iter().map(|wav| wav.id()).collect();
+    let wavs = wav_ids.into_iter().filter_map(|id| header.wav_file(id));
+    for filename in wavs {
+        if !resource_exists(dir, &filename, AUDIO_EXTENSIONS) {
+            errors.push(ValidationError::MissingResource { filename });
+        }
+    }
+    let image_ids: std::collections::BTreeSet<_> = (header.bmps().iter().map(|bmp| bmp.id()))
+        .chain(header.exbmps().iter().map(|exbmp| exbmp.id()))
+        .collect();
+    // Image filenames are kept as written, so Windows separators are
+    // converted here, as `Wav::path` does for sounds.
+    let images = image_ids
+        .into_iter()
+        .filter_map(|id| header.bga_image(id))
+        .map(|image| image.filename().replace('\\', "/"));
+    for filename in images {
+        if !resource_exists(dir, &filename, IMAGE_EXTENSIONS) {
+            errors.push(ValidationError::MissingResource { filename });
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(feature = "std")]
+const AUDIO_EXTENSIONS: &[&str] = &["wav", "ogg", "flac", "mp3"];
+
+#[cfg(feature = "std")]
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "bmp", "png", "jpg", "jpeg", "gif", "tga", "dds", "mpg", "mpeg", "mp4", "avi", "wmv", "webm",
+];
+
+#[cfg(feature = "std")]
+fn resource_exists(dir: &std::path::Path, filename: &str, extensions: &[&str]) -> bool {
+    let path = dir.join(filename);
+    path.is_file()
+        || extensions
+            .iter()
+            .any(|extension| path.with_extension(extension).is_file())
+}
+
+struct Playable<'h, 'e> {
+    header: &'h Header,
+    notes: usize,
+    /// Undefined ids already reported, by channel, so each is reported once.
+    undefined: BTreeSet<(String, ObjectId)>,
+    errors: &'e mut Vec<ValidationError>,
+}
+
+impl HeaderVisitor for Playable<'_, '_> {
+    fn on_channel(&mut self, _measure: &str, channel: &str, data: &str) -> ControlFlow<()> {
+        // Landmine data is damage rather than a sound, and the rest aren't
+        // sounds at all.
+        let plays_wav = channel == "01" || (note_side(channel).is_some() && !is_landmine(channel));
+        if !plays_wav {
+            return ControlFlow::Continue(());
+        }
+        let Ok(ChannelData::Base36(ids)) = ChannelData::parse(channel, data, self.header.base())
+        else {
+            return ControlFlow::Continue(());
+        };
+
+        for &id in ids.iter().filter(|&&id| id != ObjectId::ZERO) {
+            if is_judged(channel) {
+                self.notes += 1;
+            }
+            if self.header.wav(id).is_none() && self.undefined.insert((channel.to_string(), id)) {
+                self.errors.push(ValidationError::UndefinedWav {
+                    channel: channel.to_string(),
+                    id,
+                });
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+fn is_landmine(channel: &str) -> bool {
+    channel.starts_with(['D', 'd', 'E', 'e'])
+}
+
+/// Visible and long note channels, the ones the player is judged on.
+pub(crate) fn is_judged(channel: &str) -> bool {
+    note_side(channel).is_some() && channel.starts_with(['1', '2', '5', '6'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{HeaderScan, parse_header};
+    use alloc::vec;
+
+    fn header(input: &str) -> Header {
+        parse_header(input, HeaderScan::WholeFile, &mut Vec::new()).unwrap()
+    }
+
+    fn player_side(input: &str) -> Vec<ParseWarning> {
+        let mut warnings = Vec::new();
+        check_player_side(input, &header(input), &mut warnings);
+        warnings
+    }
+
+    #[test]
+    fn player_side_matches() {
+        assert!(player_side("#PLAYER 1\n#00111:01\n").is_empty());
+        assert!(player_side("#PLAYER 3\n#00111:01\n#00121:01\n").is_empty());
+        assert!(player_side("#PLAYER 4\n#00111:01\n").is_empty());
+        // An empty 2P line doesn't count as using the side.
+        assert!(player_side("#PLAYER 1\n#00111:01\n#00121:00\n").is_empty());
+        // pop'n music's nine buttons spill onto 2P lanes 22 to 25.
+        assert!(player_side("#PLAYER 1\n#00111:01\n#00122:01\n#00125:01\n").is_empty());
+    }
+
+    #[test]
+    fn player_side_mismatch() {
+        assert_eq!(
+            player_side("#PLAYER 1\n#00111:01\n#00161:01\n"),
+            [ParseWarning::PlayerSideMismatch {
+                player: Player::One,
+                uses_p2: true
+            }]
+        );
+        // Lane 26 is a 2P scratch, which pop'n music doesn't have.
+        assert_eq!(
+            player_side("#PLAYER 1\n#00111:01\n#00122:01\n#00126:01\n"),
+            [ParseWarning::PlayerSideMismatch {
+                player: Player::One,
+                uses_p2: true
+            }]
+        );
+        assert_eq!(
+            player_side("#PLAYER 3\n#00111:01\n"),
+            [ParseWarning::PlayerSideMismatch {
+                player: Player::Three,
+                uses_p2: false
+            }]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn missing_resources_are_reported_once_per_id() {
+        let input = "#WAV01 a.wav\n#WAV01 b.wav\n#BMP01 a.bmp\n#BMP01 b.bmp\n#00111:01\n";
+        let dir = std::env::temp_dir().join("bmrs-missing-resources");
+        let errors = check_playable_in(input, &header(input), &dir).unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                ValidationError::MissingResource {
+                    filename: "b.wav".to_string()
+                },
+                ValidationError::MissingResource {
+                    filename: "b.bmp".to_string()
+                },
+            ]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn missing_exbmp_is_reported() {
+        let input = "#WAV01 a.wav\n#BMP02 a.bmp\n#EXBMP02 0,0,0,0 b.bmp\n#EXBMP03 0,0,0,0 c.bmp\n#00111:01\n";
+        let dir = std::env::temp_dir().join("bmrs-missing-resources");
+        let errors = check_playable_in(input, &header(input), &dir).unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                ValidationError::MissingResource {
+                    filename: "a.wav".to_string()
+                },
+                ValidationError::MissingResource {
+                    filename: "b.bmp".to_string()
+                },
+                ValidationError::MissingResource {
+                    filename: "c.bmp".to_string()
+                },
+            ]
+        );
+    }
+
+    fn subdivisions(input: &str) -> Vec<ParseWarning> {
+        let mut warnings = Vec::new();
+        check_subdivisions(input, &mut warnings);
+        warnings
+    }
+
+    #[test]
+    fn consistent_subdivisions() {
+        assert!(subdivisions("#00102:0.5\n#00102:.50\n#00111:0101\n#00112:010101\n").is_empty());
+    }
+
+    #[test]
+    fn inconsistent_subdivisions() {
+        assert_eq!(
+            subdivisions("#00111:010\n#00102:0.5\n#00102:0.75\n#00202:0.75\n"),
+            [
+                ParseWarning::InconsistentSubdivision {
+                    measure: "001".to_string(),
+                    channel: "11".to_string()
+                },
+                ParseWarning::InconsistentSubdivision {
+                    measure: "001".to_string(),
+                    channel: "02".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn high_playlevel() {
+        let playlevel = |input: &str| {
+            let mut warnings = Vec::new();
+            check_playlevel(&header(input), 12, &mut warnings);
+            warnings
+        };
+        assert!(playlevel("#PLAYLEVEL 12\n").is_empty());
+        assert!(playlevel("#PLAYLEVEL 0\n").is_empty());
+        assert!(playlevel("#PLAYLEVEL -1\n").is_empty());
+        assert_eq!(
+            playlevel("#PLAYLEVEL 25\n"),
+            [ParseWarning::HighPlayLevel { level: 25 }]
+        );
+    }
+
+    #[test]
+    fn playable_chart() {
+        let input = "#BPM 150\n#BPM01 -120\n#WAV01 a.wav\n#00101:01\n#00111:01\n";
+        assert_eq!(check_playable(input, &header(input)), Ok(()));
+    }
+
+    #[test]
+    fn unplayable_chart() {
+        let input = "#BPM 0\n#BPM01 0\n#WAV01 a.wav\n#00101:0202\n#00101:02\n#00131:01\n";
+        let id = ObjectId::from_base36("02").unwrap();
+        assert_eq!(
+            check_playable(input, &header(input)),
+            Err(vec![
+                ValidationError::InvalidBpm { bpm: 0.0 },
+                ValidationError::InvalidBpm { bpm: 0.0 },
+                ValidationError::UndefinedWav {
+                    channel: "01".to_string(),
+                    id
+                },
+                ValidationError::NoNotes,
+            ])
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn backslash_paths_resolve() {
+        let dir = std::env::temp_dir().join("bmrs-backslash-paths");
+        std::fs::create_dir_all(dir.join("drums")).unwrap();
+        std::fs::write(dir.join("drums/kick.ogg"), []).unwrap();
+
+        std::fs::write(dir.join("drums/stage.png"), []).unwrap();
+
+        let input = "#WAV01 drums\\kick.wav\n#BMP01 drums\\stage.bmp\n#00111:01\n";
+        assert_eq!(check_playable_in(input, &header(input), &dir), Ok(()));
+    }
+
+    #[test]
+    fn trailing_whitespace() {
+        let input = "#00111:0011 \n#00112:0011\n#00113:0011\t\r\n";
+        let mut warnings = Vec::new();
+        check_trailing_whitespace(input, &mut warnings);
+        assert_eq!(
+            warnings,
+            [
+                ParseWarning::TrailingWhitespace {
+                    measure: "001".to_string(),
+                    channel: "11".to_string()
+                },
+                ParseWarning::TrailingWhitespace {
+                    measure: "001".to_string(),
+                    channel: "13".to_string()
+                },
+            ]
+        );
+
+        let LineKind::Channel { channel, data, .. } = classify_line("#00111:0011 ") else {
+            panic!();
+        };
+        let data = ChannelData::parse(channel, data, &Base::Base36).unwrap();
+        assert_eq!((data.len(), data.object_count()), (2, 1));
+        assert_eq!(
+            ChannelData::parse("11", "0011 ", &Base::Base36).unwrap(),
+            data
+        );
+    }
+}
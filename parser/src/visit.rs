@@ -0,0 +1,362 @@
+use core::ops::{ControlFlow, RangeInclusive};
+
+use crate::line::{LineKind, classify_line};
+
+/// Callbacks for [`parse_visit`], called as each command is come across.
+///
+/// Every callback defaults to carrying on, so implement only the ones you
+/// care about. Return `ControlFlow::Break` to stop scanning early, e.g. once
+/// you have everything a song list needs.
+///
+/// Values are the raw text of the argument with surrounding whitespace
+/// trimmed. Nothing is parsed or allocated on your behalf. Object ids are
+/// passed as written too, which is one character rather than two in some old
+/// charts, as in `#WAV1`.
+pub trait HeaderVisitor {
+    /// `#PLAYER n`
+    fn on_player(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#BASE n`
+    fn on_base(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#RANK n`
+    fn on_rank(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#DEFEXRANK n`
+    fn on_defexrank(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#TOTAL n`
+    fn on_total(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#VOLWAV n`
+    fn on_volwav(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#STAGEFILE imagefilename`
+    fn on_stagefile(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#BANNER imagefilename`
+    fn on_banner(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#BACKBMP imagefilename`
+    fn on_backbmp(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#PLAYLEVEL n`
+    fn on_playlevel(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#DIFFICULTY n`
+    fn on_difficulty(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#TITLE string`
+    fn on_title(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#SUBTITLE string`
+    fn on_subtitle(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#ARTIST string`
+    fn on_artist(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#SUBARTIST string`
+    fn on_subartist(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#MAKER string`
+    fn on_maker(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#GENRE string`
+    fn on_genre(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#URL string`
+    fn on_url(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#EMAIL string`
+    fn on_email(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#BPM n`
+    fn on_bpm(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#LNTYPE n`
+    fn on_lntype(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#LNOBJ xx`
+    fn on_lnobj(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#LNMODE n`
+    fn on_lnmode(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#DIVIDEPROP n`
+    fn on_divideprop(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#ExtChr ...`
+    fn on_extchr(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#OPTION name...`
+    fn on_option(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#PATH_WAV directory`
+    fn on_path_wav(&mut self, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#WAVxx filename`
+    fn on_wav(&mut self, _id: &str, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#BMPxx filename`
+    fn on_bmp(&mut self, _id: &str, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#EXBMPxx a,r,g,b filename`
+    fn on_exbmp(&mut self, _id: &str, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#ARGBx a,r,g,b`
+    fn on_argb(&mut self, _layer: &str, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#BPMxx n` or `#EXBPMxx n`
+    fn on_exbpm(&mut self, _id: &str, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// `#STOPxx n`
+    fn on_stop(&mut self, _id: &str, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// Any other `#` command. `command` is the name without the `#`.
+    fn on_unknown(&mut self, _command: &str, _value: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// A channel-data line, `#xxxCC:data`.
+    fn on_channel(&mut self, _measure: &str, _channel: &str, _data: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+}
+
+/// Scans `input` line by line, calling `visitor` for each command found.
+///
+/// Lines not starting with `#` are comments as far as BMS is concerned, and
+/// are skipped. Command names are case insensitive. A UTF-8 byte order mark
+/// at the start of `input`, as some editors save, is skipped too.
+///
+/// Returns `ControlFlow::Break` if the visitor stopped the scan early.
+pub fn parse_visit(input: &str, visitor: &mut impl HeaderVisitor) -> ControlFlow<()> {
+    let input = input.strip_prefix('\u{feff}').unwrap_or(input);
+    for line in input.lines() {
+        match classify_line(line) {
+            LineKind::Blank | LineKind::Comment => {}
+            LineKind::Command { name, value } => visit_command(name, value, visitor)?,
+            LineKind::Channel {
+                measure,
+                channel,
+                data,
+            } => visitor.on_channel(measure, channel, data)?,
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+fn visit_command(command: &str, value: &str, visitor: &mut impl HeaderVisitor) -> ControlFlow<()> {
+    let is = |name: &str| command.eq_ignore_ascii_case(name);
+    if is("PLAYER") {
+        visitor.on_player(value)
+    } else if is("BASE") {
+        visitor.on_base(value)
+    } else if is("RANK") {
+        visitor.on_rank(value)
+    } else if is("DEFEXRANK") {
+        visitor.on_defexrank(value)
+    } else if is("TOTAL") {
+        visitor.on_total(value)
+    } else if is("VOLWAV") {
+        visitor.on_volwav(value)
+    } else if is("STAGEFILE") {
+        visitor.on_stagefile(value)
+    } else if is("BANNER") {
+        visitor.on_banner(value)
+    } else if is("BACKBMP") {
+        visitor.on_backbmp(value)
+    } else if is("PLAYLEVEL") {
+        visitor.on_playlevel(value)
+    } else if is("DIFFICULTY") {
+        visitor.on_difficulty(value)
+    } else if is("TITLE") {
+        visitor.on_title(value)
+    } else if is("SUBTITLE") {
+        visitor.on_subtitle(value)
+    } else if is("ARTIST") {
+        visitor.on_artist(value)
+    } else if is("SUBARTIST") {
+        visitor.on_subartist(value)
+    } else if is("MAKER") {
+        visitor.on_maker(value)
+    } else if is("GENRE") {
+        visitor.on_genre(value)
+    } else if is("URL") {
+        visitor.on_url(value)
+    } else if is("EMAIL") {
+        visitor.on_email(value)
+    } else if is("BPM") {
+        visitor.on_bpm(value)
+    } else if is("LNTYPE") {
+        visitor.on_lntype(value)
+    } else if is("LNOBJ") {
+        visitor.on_lnobj(value)
+    } else if is("LNMODE") {
+        visitor.on_lnmode(value)
+    } else if is("DIVIDEPROP") {
+        visitor.on_divideprop(value)
+    } else if is("EXTCHR") {
+        visitor.on_extchr(value)
+    } else if is("OPTION") {
+        visitor.on_option(value)
+    } else if is("PATH_WAV") {
+        visitor.on_path_wav(value)
+    } else if let Some(id) = strip_id(command, "EXBMP", 1..=2) {
+        visitor.on_exbmp(id, value)
+    } else if let Some(id) = strip_id(command, "EXBPM", 1..=2) {
+        visitor.on_exbpm(id, value)
+    } else if let Some(id) = strip_id(command, "WAV", 1..=2) {
+        visitor.on_wav(id, value)
+    } else if let Some(id) = strip_id(command, "BMP", 1..=2) {
+        visitor.on_bmp(id, value)
+    } else if let Some(id) = strip_id(command, "BPM", 1..=2) {
+        visitor.on_exbpm(id, value)
+    } else if let Some(id) = strip_id(command, "STOP", 1..=2) {
+        visitor.on_stop(id, value)
+    } else if let Some(layer) = strip_id(command, "ARGB", 1..=1) {
+        visitor.on_argb(layer, value)
+    } else {
+        visitor.on_unknown(command, value)
+    }
+}
+
+/// Splits `NAMExx` into `xx`, if `command` is `name` followed by an id of
+/// `id_len` characters.
+fn strip_id<'a>(command: &'a str, name: &str, id_len: RangeInclusive<usize>) -> Option<&'a str> {
+    let prefix = command.get(..name.len())?;
+    let id = command.get(name.len()..)?;
+    (prefix.eq_ignore_ascii_case(name) && id_len.contains(&id.chars().count())).then_some(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+
+    /// Reads the title and stops, noting every command it sees.
+    #[derive(Default)]
+    struct TitleOnly {
+        title: Option<String>,
+        seen: Vec<String>,
+    }
+
+    impl HeaderVisitor for TitleOnly {
+        fn on_title(&mut self, value: &str) -> ControlFlow<()> {
+            self.seen.push("TITLE".to_string());
+            self.title = Some(value.to_string());
+            ControlFlow::Break(())
+        }
+
+        fn on_artist(&mut self, _value: &str) -> ControlFlow<()> {
+            self.seen.push("ARTIST".to_string());
+            ControlFlow::Continue(())
+        }
+
+        fn on_genre(&mut self, _value: &str) -> ControlFlow<()> {
+            self.seen.push("GENRE".to_string());
+            ControlFlow::Continue(())
+        }
+    }
+
+    #[test]
+    fn stops_after_the_title() {
+        let mut visitor = TitleOnly::default();
+        let flow = parse_visit("#ARTIST a\n#title  song \n#GENRE g\n", &mut visitor);
+        assert_eq!(flow, ControlFlow::Break(()));
+        assert_eq!(visitor.title.as_deref(), Some("song"));
+        assert_eq!(visitor.seen, ["ARTIST", "TITLE"]);
+    }
+
+    #[test]
+    fn visits_everything_without_a_break() {
+        let mut visitor = TitleOnly::default();
+        let flow = parse_visit("#GENRE g\n#ARTIST a\n", &mut visitor);
+        assert_eq!(flow, ControlFlow::Continue(()));
+        assert_eq!(visitor.seen, ["GENRE", "ARTIST"]);
+    }
+
+    #[test]
+    fn skips_a_byte_order_mark() {
+        let mut visitor = TitleOnly::default();
+        let _ = parse_visit("\u{feff}#TITLE x\n", &mut visitor);
+        assert_eq!(visitor.title.as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn id_commands() {
+        assert_eq!(strip_id("WAV0A", "WAV", 2..=2), Some("0A"));
+        assert_eq!(strip_id("wav0a", "WAV", 2..=2), Some("0a"));
+        assert_eq!(strip_id("WAV0", "WAV", 2..=2), None);
+        assert_eq!(strip_id("WAV0", "WAV", 1..=2), Some("0"));
+        assert_eq!(strip_id("WAV", "WAV", 1..=2), None);
+        assert_eq!(strip_id("ARGBB", "ARGB", 1..=1), Some("B"));
+    }
+}